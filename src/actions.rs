@@ -1,5 +1,14 @@
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy::window::PrimaryWindow;
+use leafwing_input_manager::buttonlike::ButtonState;
+use leafwing_input_manager::plugin::InputManagerSystem;
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+use crate::ui::UiButton;
+use crate::{GameState, PauseState};
 
 pub struct ActionsPlugin;
 impl Plugin for ActionsPlugin {
@@ -9,11 +18,24 @@ impl Plugin for ActionsPlugin {
 
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, startup)
-            .add_plugins(InputManagerPlugin::<UiButtonAction>::default());
+            .add_systems(
+                Update,
+                (
+                    apply_bindings.run_if(resource_changed::<Settings>),
+                    toggle_pause.run_if(in_state(GameState::Playing)),
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                touch_lanes.in_set(InputManagerSystem::ManualControl),
+            )
+            .add_plugins(InputManagerPlugin::<UiButtonAction>::default())
+            .add_plugins(InputManagerPlugin::<NavigationAction>::default())
+            .init_resource::<TouchLanes>();
     }
 }
 
-#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect)]
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect, Serialize, Deserialize)]
 pub enum UiButtonAction {
     One,
     Two,
@@ -21,15 +43,6 @@ pub enum UiButtonAction {
     Four,
 }
 impl UiButtonAction {
-    fn init() -> InputMap<UiButtonAction> {
-        InputMap::new([
-            (UiButtonAction::One, KeyCode::KeyJ),
-            (UiButtonAction::Two, KeyCode::KeyK),
-            (UiButtonAction::Three, KeyCode::KeyL),
-            (UiButtonAction::Four, KeyCode::Semicolon),
-        ])
-    }
-
     pub fn array() -> [UiButtonAction; 4] {
         [
             UiButtonAction::One,
@@ -49,9 +62,407 @@ impl UiButtonAction {
     }
 }
 
-fn startup(mut commands: Commands) {
+// Moving around menus, separate from the rhythm buttons so it works the same everywhere
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect)]
+pub enum NavigationAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+    Pause,
+}
+impl NavigationAction {
+    const KEYS: [(NavigationAction, KeyCode); 10] = [
+        (NavigationAction::Up, KeyCode::ArrowUp),
+        (NavigationAction::Down, KeyCode::ArrowDown),
+        (NavigationAction::Left, KeyCode::ArrowLeft),
+        (NavigationAction::Right, KeyCode::ArrowRight),
+        (NavigationAction::Confirm, KeyCode::Enter),
+        (NavigationAction::Confirm, KeyCode::Space),
+        (NavigationAction::Back, KeyCode::Escape),
+        (NavigationAction::Back, KeyCode::Backspace),
+        (NavigationAction::Pause, KeyCode::Escape),
+        (NavigationAction::Pause, KeyCode::KeyP),
+    ];
+
+    fn init() -> InputMap<NavigationAction> {
+        use NavigationAction::*;
+        InputMap::new(Self::KEYS).with_multiple([
+            (Up, GamepadButton::DPadUp),
+            (Down, GamepadButton::DPadDown),
+            (Left, GamepadButton::DPadLeft),
+            (Right, GamepadButton::DPadRight),
+            (Confirm, GamepadButton::South),
+            (Back, GamepadButton::East),
+            (Pause, GamepadButton::Start),
+        ])
+    }
+
+    // Keys the rhythm buttons can't take, one press would fire both actions
+    fn reserves(key: KeyCode) -> bool {
+        Self::KEYS.iter().any(|(_, reserved)| *reserved == key)
+    }
+}
+
+// Inputs bound to every `UiButtonAction`, saved with the `Settings`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bindings(pub HashMap<UiButtonAction, ActionBinding>);
+impl Default for Bindings {
+    fn default() -> Self {
+        use GamepadButton::*;
+        let defaults = [
+            (KeyCode::KeyJ, West, LeftTrigger2),
+            (KeyCode::KeyK, South, LeftTrigger),
+            (KeyCode::KeyL, North, RightTrigger),
+            (KeyCode::Semicolon, East, RightTrigger2),
+        ];
+        Self(
+            UiButtonAction::array()
+                .into_iter()
+                .zip(defaults)
+                .map(|(action, (key, face, trigger))| {
+                    let lanes = UiButtonAction::array().len() as f32;
+                    let binding = ActionBinding {
+                        keys: vec![key],
+                        buttons: vec![face, trigger],
+                        touch: TouchZone {
+                            left: action.index() as f32 / lanes,
+                            right: (action.index() + 1) as f32 / lanes,
+                        },
+                    };
+                    (action, binding)
+                })
+                .collect(),
+        )
+    }
+}
+impl Bindings {
+    pub fn get(&self, action: UiButtonAction) -> Option<&ActionBinding> {
+        self.0.get(&action)
+    }
+
+    pub fn input_map(&self) -> InputMap<UiButtonAction> {
+        let mut input_map = InputMap::default();
+        for (action, binding) in &self.0 {
+            for key in &binding.keys {
+                input_map.insert(*action, *key);
+            }
+            for button in &binding.buttons {
+                input_map.insert(*action, *button);
+            }
+        }
+        input_map
+    }
+
+    // Binds `input` to `action` alone. When another action already used it, that action takes
+    // over the inputs `action` had before, so no two actions share an input. Keys used by the
+    // navigation actions are refused and given back as the error.
+    pub fn rebind(
+        &mut self,
+        action: UiButtonAction,
+        input: BindingInput,
+    ) -> Result<Option<UiButtonAction>, KeyCode> {
+        match input {
+            BindingInput::Key(key) if NavigationAction::reserves(key) => Err(key),
+            input => Ok(self.swap(action, input)),
+        }
+    }
+
+    fn swap(&mut self, action: UiButtonAction, input: BindingInput) -> Option<UiButtonAction> {
+        let previous = self.0.get(&action)?.clone();
+        let conflict = self
+            .0
+            .iter()
+            .find(|(other, binding)| **other != action && binding.contains(input))
+            .map(|(other, _)| *other);
+        if let Some(other) = conflict {
+            let binding = self.0.get_mut(&other)?;
+            match input {
+                BindingInput::Key(_) => binding.keys = previous.keys.clone(),
+                // The face button goes first so it's the one shown
+                BindingInput::Button(_) => {
+                    binding
+                        .buttons
+                        .retain(|button| BindingInput::Button(*button) != input);
+                    if let Some(face) = previous.buttons.first() {
+                        binding.buttons.insert(0, *face);
+                    }
+                }
+            }
+        }
+        let binding = self.0.get_mut(&action)?;
+        match input {
+            BindingInput::Key(key) => binding.keys = vec![key],
+            // The trigger stays as a second button, binding the trigger itself swaps the two
+            BindingInput::Button(button) if binding.buttons.contains(&button) => {
+                binding.buttons.retain(|other| *other != button);
+                binding.buttons.insert(0, button);
+            }
+            BindingInput::Button(button) => match binding.buttons.first_mut() {
+                Some(first) => *first = button,
+                None => binding.buttons.push(button),
+            },
+        }
+        conflict
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActionBinding {
+    pub keys: Vec<KeyCode>,
+    pub buttons: Vec<GamepadButton>,
+    pub touch: TouchZone,
+}
+impl ActionBinding {
+    fn contains(&self, input: BindingInput) -> bool {
+        match input {
+            BindingInput::Key(key) => self.keys.contains(&key),
+            BindingInput::Button(button) => self.buttons.contains(&button),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        let key = self.keys.first().map(|key| key_label(*key));
+        let button = self.buttons.first().map(|button| format!("{button:?}"));
+        [key, button]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+}
+
+// Horizontal band of the screen, as fractions of its width, that presses the action when touched
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TouchZone {
+    pub left: f32,
+    pub right: f32,
+}
+impl TouchZone {
+    fn contains(&self, fraction: f32) -> bool {
+        (self.left..self.right).contains(&fraction)
+    }
+}
+
+// Actions held down by touches last frame
+#[derive(Resource, Default, Deref, DerefMut)]
+struct TouchLanes(HashSet<UiButtonAction>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingInput {
+    Key(KeyCode),
+    Button(GamepadButton),
+}
+
+pub fn key_label(key: KeyCode) -> String {
+    match key {
+        KeyCode::Semicolon => ";".to_string(),
+        key => {
+            let name = format!("{key:?}");
+            name.strip_prefix("Key")
+                .or(name.strip_prefix("Digit"))
+                .unwrap_or(&name)
+                .to_string()
+        }
+    }
+}
+
+// SYSTEMS
+
+fn startup(mut commands: Commands, settings: Res<Settings>) {
     commands.spawn((
         Name::new("Input Manager: Combat Buttons"),
-        InputManagerBundle::with_map(UiButtonAction::init()),
+        InputManagerBundle::with_map(settings.bindings.input_map()),
+    ));
+    commands.spawn((
+        Name::new("Input Manager: Navigation"),
+        InputManagerBundle::with_map(NavigationAction::init()),
     ));
 }
+
+fn toggle_pause(
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    query_navigation: Query<&ActionState<NavigationAction>>,
+) {
+    if query_navigation
+        .get_single()
+        .is_ok_and(|action_state| action_state.just_pressed(&NavigationAction::Pause))
+    {
+        next_pause_state.set(match pause_state.get() {
+            PauseState::Unpaused => PauseState::Paused,
+            PauseState::Paused => PauseState::Unpaused,
+        });
+    }
+}
+
+// Every touch presses the `UiButton` under it, or while playing the lane its `TouchZone` covers
+// when it's not on any button, so several fingers can hold a chord
+#[allow(clippy::too_many_arguments)]
+fn touch_lanes(
+    touches: Res<Touches>,
+    settings: Res<Settings>,
+    game_state: Res<State<GameState>>,
+    mut touch_lanes: ResMut<TouchLanes>,
+    query_window: Query<&Window, With<PrimaryWindow>>,
+    query_camera: Query<&Camera, With<IsDefaultUiCamera>>,
+    query_ui_button: Query<(
+        &UiButton,
+        &ComputedNode,
+        &GlobalTransform,
+        &InheritedVisibility,
+    )>,
+    query_interaction: Query<
+        (&ComputedNode, &GlobalTransform, &InheritedVisibility),
+        With<Interaction>,
+    >,
+    mut query_action_state: Query<&mut ActionState<UiButtonAction>>,
+) {
+    let Ok(window) = query_window.get_single() else {
+        return;
+    };
+    // UI nodes are laid out in physical pixels from the corner of the camera viewport
+    let viewport = query_camera
+        .get_single()
+        .ok()
+        .and_then(|camera| camera.physical_viewport_rect())
+        .map_or(Vec2::ZERO, |rect| rect.min.as_vec2());
+    let contains = |node: &ComputedNode, transform: &GlobalTransform, position: Vec2| {
+        Rect::from_center_size(transform.translation().truncate(), node.size()).contains(position)
+    };
+
+    let mut touched = HashSet::new();
+    for touch in touches.iter() {
+        let position = touch.position() * window.scale_factor() - viewport;
+        let button = query_ui_button
+            .iter()
+            .find(|(_, node, transform, visibility)| {
+                visibility.get() && contains(node, transform, position)
+            })
+            .and_then(|(ui_button, ..)| UiButtonAction::array().get(***ui_button).copied());
+        if let Some(action) = button {
+            touched.insert(action);
+            continue;
+        }
+        // Touches on menu buttons are left to `Interaction`, and away from the buttons menus
+        // shouldn't react at all
+        if *game_state.get() != GameState::Playing
+            || query_interaction
+                .iter()
+                .any(|(node, transform, visibility)| {
+                    visibility.get() && contains(node, transform, position)
+                })
+        {
+            continue;
+        }
+        let fraction = touch.position().x / window.width();
+        touched.extend(
+            settings
+                .bindings
+                .0
+                .iter()
+                .filter(|(_, binding)| binding.touch.contains(fraction))
+                .map(|(action, _)| *action),
+        );
+    }
+
+    for mut action_state in &mut query_action_state {
+        for action in &touched {
+            if touch_lanes.contains(action) {
+                // The input map released it this frame, but the finger never lifted
+                if let Some(button_data) = action_state.button_data_mut(action) {
+                    button_data.state = ButtonState::Pressed;
+                }
+            } else {
+                action_state.press(action);
+            }
+        }
+    }
+    **touch_lanes = touched;
+}
+
+fn apply_bindings(
+    settings: Res<Settings>,
+    mut query_input_map: Query<&mut InputMap<UiButtonAction>>,
+) {
+    for mut input_map in &mut query_input_map {
+        *input_map = settings.bindings.input_map();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buttons(bindings: &Bindings, action: UiButtonAction) -> Vec<GamepadButton> {
+        bindings.get(action).unwrap().buttons.clone()
+    }
+
+    #[test]
+    fn displaced_action_shows_the_face_button_it_takes_over() {
+        let mut bindings = Bindings::default();
+        let swapped = bindings.rebind(
+            UiButtonAction::One,
+            BindingInput::Button(GamepadButton::South),
+        );
+        assert_eq!(swapped, Ok(Some(UiButtonAction::Two)));
+        assert_eq!(
+            buttons(&bindings, UiButtonAction::One),
+            [GamepadButton::South, GamepadButton::LeftTrigger2]
+        );
+        assert_eq!(
+            buttons(&bindings, UiButtonAction::Two),
+            [GamepadButton::West, GamepadButton::LeftTrigger]
+        );
+        assert_eq!(
+            bindings.get(UiButtonAction::Two).unwrap().label(),
+            "K / West"
+        );
+    }
+
+    #[test]
+    fn rebinding_to_own_trigger_keeps_the_face_button() {
+        let mut bindings = Bindings::default();
+        let swapped = bindings.rebind(
+            UiButtonAction::One,
+            BindingInput::Button(GamepadButton::LeftTrigger2),
+        );
+        assert_eq!(swapped, Ok(None));
+        assert_eq!(
+            buttons(&bindings, UiButtonAction::One),
+            [GamepadButton::LeftTrigger2, GamepadButton::West]
+        );
+    }
+
+    #[test]
+    fn displaced_key_takes_over_the_previous_keys() {
+        let mut bindings = Bindings::default();
+        let swapped = bindings.rebind(UiButtonAction::One, BindingInput::Key(KeyCode::KeyK));
+        assert_eq!(swapped, Ok(Some(UiButtonAction::Two)));
+        assert_eq!(
+            bindings.get(UiButtonAction::One).unwrap().keys,
+            [KeyCode::KeyK]
+        );
+        assert_eq!(
+            bindings.get(UiButtonAction::Two).unwrap().keys,
+            [KeyCode::KeyJ]
+        );
+    }
+
+    #[test]
+    fn navigation_keys_are_refused() {
+        let mut bindings = Bindings::default();
+        for key in [
+            KeyCode::Enter,
+            KeyCode::Space,
+            KeyCode::ArrowUp,
+            KeyCode::KeyP,
+        ] {
+            let rebound = bindings.rebind(UiButtonAction::One, BindingInput::Key(key));
+            assert_eq!(rebound, Err(key));
+        }
+        assert_eq!(bindings, Bindings::default());
+    }
+}
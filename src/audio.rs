@@ -1,5 +1,14 @@
+use crate::chart::{ChartAsset, Difficulty};
+use crate::loading::AudioAssets;
+use crate::settings::{AudioBus, Settings};
+use crate::song::{SongAsset, TempoChange};
+use crate::tempo::{TempoMap, EPSILON};
+use crate::{GameState, PauseState, SettingsState};
 use bevy::prelude::*;
-use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl, AudioSource};
+use bevy::utils::HashMap;
+use bevy_kira_audio::{
+    AudioApp, AudioChannel, AudioControl, AudioInstance, AudioSource, PlaybackState,
+};
 use std::time::Duration;
 
 pub struct InternalAudioPlugin;
@@ -10,16 +19,47 @@ impl Plugin for InternalAudioPlugin {
 
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, startup)
-            .add_systems(FixedUpdate, tick_metronome)
-            .add_systems(Update, (evr_control_metronome, update_note_timers))
+            .add_systems(
+                Update,
+                (evr_control_metronome, update_tempo_map, tick_metronome)
+                    .chain()
+                    .in_set(MetronomeSet),
+            )
+            .add_systems(OnExit(GameState::Loading), load_current_song)
+            .add_systems(
+                Update,
+                apply_volume.run_if(resource_changed::<Settings>.or(resource_changed::<MusicDuck>)),
+            )
+            .add_systems(
+                Update,
+                evr_play_clicks
+                    .after(MetronomeSet)
+                    .run_if(not(in_state(SettingsState::Calibration))),
+            )
+            .add_systems(OnEnter(PauseState::Paused), pause_metronome)
+            .add_systems(OnEnter(PauseState::Unpaused), resume_metronome)
             .init_resource::<CurrentSong>()
-            .init_resource::<MetronomeAudioChannel>()
+            .init_resource::<MusicAudioChannel>()
+            .init_resource::<SfxAudioChannel>()
+            .init_resource::<ClickAudioChannel>()
+            .init_resource::<UiAudioChannel>()
+            .init_resource::<MetronomeOffset>()
+            .init_resource::<MusicDuck>()
             .init_state::<MetronomeState>()
-            .add_audio_channel::<MetronomeAudioChannel>()
-            .add_event::<MetronomeEvent>();
+            .add_audio_channel::<MusicAudioChannel>()
+            .add_audio_channel::<SfxAudioChannel>()
+            .add_audio_channel::<ClickAudioChannel>()
+            .add_audio_channel::<UiAudioChannel>()
+            .add_event::<MetronomeEvent>()
+            .add_event::<BeatEvent>()
+            .add_event::<ClickEvent>();
     }
 }
 
+// Systems reacting to the beat should run after this set to see the current song position
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MetronomeSet;
+
 #[derive(States, Default, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum MetronomeState {
     #[default]
@@ -30,7 +70,7 @@ pub enum MetronomeState {
 
 fn startup(mut commands: Commands, current_song: Res<CurrentSong>) {
     if let Some(song) = &current_song.0 {
-        commands.spawn(MetronomeBundle::new(&song.info));
+        commands.spawn(MetronomeBundle::new(song));
     } else {
         commands.spawn(MetronomeBundle::default());
     }
@@ -38,53 +78,168 @@ fn startup(mut commands: Commands, current_song: Res<CurrentSong>) {
 }
 
 #[derive(Event, Deref)]
-struct MetronomeEvent(MetronomeCommand);
+pub struct MetronomeEvent(pub MetronomeCommand);
+
+// Sent every time a note of `kind` begins. `beat` is the index of that note within the
+// measure, `measure` counts from 0 and `position` is the song position in seconds.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BeatEvent {
+    pub kind: NoteKind,
+    pub beat: u32,
+    pub measure: u32,
+    pub position: f32,
+}
+
+// Sent alongside `BeatEvent` as the playback position, ahead of the song position by the audio
+// latency, reaches each note
+#[derive(Event, Deref)]
+struct ClickEvent(BeatEvent);
 
-#[allow(dead_code)] // TODO:
-enum MetronomeCommand {
+pub enum MetronomeCommand {
     Play(Song),
     Pause,
     Resume,
+    // Leave the body loop on the next measure and play the outro before stopping
+    Finish,
     Stop,
 }
 impl MetronomeCommand {
-    fn state(&self) -> MetronomeState {
+    // Finishing carries on in whatever state the metronome is in
+    fn state(&self) -> Option<MetronomeState> {
         use MetronomeCommand::*;
         use MetronomeState::*;
         match self {
-            Play(_) => Playing,
-            Pause => Paused,
-            Resume => Playing,
-            Stop => Stopped,
+            Play(_) => Some(Playing),
+            Pause => Some(Paused),
+            Resume => Some(Playing),
+            Finish => None,
+            Stop => Some(Stopped),
         }
     }
 }
 
+// Songs, which also drive the `Metronome`
+#[derive(Clone, Copy, Default, Resource)]
+pub struct MusicAudioChannel;
+
+#[derive(Clone, Copy, Default, Resource)]
+pub struct SfxAudioChannel;
+
+// Metronome clicks
+#[derive(Clone, Copy, Default, Resource)]
+pub struct ClickAudioChannel;
+
+#[derive(Clone, Copy, Default, Resource)]
+pub struct UiAudioChannel;
+
+// Music volume on top of the settings, silenced by calibration
+#[derive(Clone, Copy, PartialEq, Resource, Deref, DerefMut)]
+pub struct MusicDuck(pub f32);
+impl Default for MusicDuck {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+// Music is ducked out as it pauses and back in as it resumes, a paused channel can't play quietly
+const PAUSE_FADE: Duration = Duration::from_millis(250);
+
+// Latency in seconds, kept in sync with the calibrated `Settings`. `audio` is subtracted from
+// the playback position so presses made on the heard beat land on it, `visual` is how far ahead
+// of the song position visuals are drawn to be seen on the beat.
 #[derive(Clone, Copy, Default, Resource)]
-struct MetronomeAudioChannel;
+pub struct MetronomeOffset {
+    pub audio: f32,
+    pub visual: f32,
+}
 
 #[derive(Component, Clone)]
-struct Song {
+pub struct Song {
     handle: Handle<AudioSource>,
     info: AudioInfo,
+    changes: Vec<TempoChange>,
+    charts: HashMap<Difficulty, Handle<ChartAsset>>,
+}
+impl Song {
+    pub fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::new(&self.info, &self.changes)
+    }
+
+    pub fn chart(&self, difficulty: Difficulty) -> Option<&Handle<ChartAsset>> {
+        self.charts.get(&difficulty)
+    }
+}
+impl From<&SongAsset> for Song {
+    fn from(asset: &SongAsset) -> Self {
+        Self {
+            handle: asset.audio.clone(),
+            info: asset.info,
+            changes: asset.changes.clone(),
+            charts: asset.charts.clone(),
+        }
+    }
 }
 
-#[allow(dead_code)] //TODO
 #[derive(Clone, Copy, Default)]
-struct AudioInfo {
-    tempo: Tempo,
-    metre: Metre,
-    intro: Option<AudioLength>,
-    body: AudioLength,
-    outro: Option<AudioLength>,
+pub struct AudioInfo {
+    pub tempo: Tempo,
+    pub metre: Metre,
+    pub intro: Option<AudioLength>,
+    pub body: AudioLength,
+    pub outro: Option<AudioLength>,
+}
+impl AudioInfo {
+    pub fn intro_length(&self) -> f32 {
+        self.intro.map_or(0.0, |length| *length)
+    }
+
+    pub fn body_end(&self) -> f32 {
+        self.intro_length() + *self.body
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+enum SongSection {
+    #[default]
+    Intro,
+    Body,
+    Outro,
+}
+
+#[derive(Clone)]
+struct Playback {
+    instance: Handle<AudioInstance>,
+    section: SongSection,
+    // Song position and measure count at playback position zero of `instance`. Both grow on
+    // every loop of the body so the metronome keeps counting across section changes.
+    offset: f32,
+    measures: i64,
+    loops: u32,
+    last_position: f32,
+    finishing: bool,
+}
+impl Playback {
+    fn new(instance: Handle<AudioInstance>) -> Self {
+        Self {
+            instance,
+            section: SongSection::Intro,
+            offset: 0.0,
+            measures: 0,
+            loops: 0,
+            last_position: 0.0,
+            finishing: false,
+        }
+    }
 }
-impl AudioInfo {}
 
 #[derive(Clone, Copy, Default, Deref, DerefMut)]
-struct AudioLength(f32);
+pub struct AudioLength(pub f32); // Seconds
 
 #[derive(Clone, Default, Resource)]
-struct CurrentSong(Option<Song>);
+pub struct CurrentSong(pub Option<Song>);
 impl CurrentSong {}
 
 #[derive(Bundle, Clone, Default)]
@@ -93,84 +248,223 @@ struct MetronomeBundle {
     metronome: Metronome,
 }
 impl MetronomeBundle {
-    fn new(audio_info: &AudioInfo) -> Self {
+    fn new(song: &Song) -> Self {
         Self {
             name: Name::new("Metronome"),
-            metronome: Metronome::new(audio_info),
+            metronome: Metronome::new(song),
         }
     }
 }
 
 #[derive(Component, Clone, Default)]
-struct Metronome {
-    whole_note: NoteTimer,
-    half_note: NoteTimer,
-    quarter_note: NoteTimer,
-    eighth_note: NoteTimer,
-    sixteenth_note: NoteTimer,
-    thirtysecond_note: NoteTimer,
-    measure_timer: NoteTimer,
+pub struct Metronome {
+    tempo_map: TempoMap,
+    playback: Option<Playback>,
+    // Song position in seconds, continuous across loops and section changes
+    position: f32,
+    visual_offset: f32,
+    // Musical position in whole notes of the audio file up to which notes have been reported,
+    // so notes skipped by a frame hitch are still emitted
+    cursor: f32,
+    // Same for clicks, which follow the playback position rather than the song position
+    click_cursor: f32,
 }
 impl Metronome {
-    fn new(audio_info: &AudioInfo) -> Self {
+    fn new(song: &Song) -> Self {
         Metronome {
-            whole_note: NoteTimer::new(NoteKind::Whole, audio_info),
-            half_note: NoteTimer::new(NoteKind::Half, audio_info),
-            quarter_note: NoteTimer::new(NoteKind::Quarter, audio_info),
-            eighth_note: NoteTimer::new(NoteKind::Eighth, audio_info),
-            sixteenth_note: NoteTimer::new(NoteKind::Sixteenth, audio_info),
-            thirtysecond_note: NoteTimer::new(NoteKind::ThirtySecond, audio_info),
-            measure_timer: NoteTimer::new(NoteKind::Measure, audio_info),
+            tempo_map: song.tempo_map(),
+            ..default()
         }
     }
 
-    fn update(&mut self, audio_info: &AudioInfo) {
-        self.whole_note.update(audio_info);
-        self.half_note.update(audio_info);
-        self.quarter_note.update(audio_info);
-        self.eighth_note.update(audio_info);
-        self.sixteenth_note.update(audio_info);
-        self.thirtysecond_note.update(audio_info);
-        self.measure_timer.update(audio_info);
+    fn update(&mut self, song: &Song) {
+        self.tempo_map = song.tempo_map();
+        self.seek(0.0);
     }
-}
 
-#[derive(Component, Clone, Default)]
-struct NoteTimer {
-    timer: Timer,
-    kind: NoteKind,
-}
-impl NoteTimer {
-    fn new(kind: NoteKind, audio_info: &AudioInfo) -> Self {
-        Self {
-            timer: Timer::new(
-                Duration::from_secs_f32(kind.length(audio_info).unwrap_or_default()),
-                TimerMode::Repeating,
-            ),
-            kind,
+    fn seek(&mut self, wholes: f32) {
+        self.cursor = wholes;
+        self.click_cursor = wholes;
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    // Song position to draw beat visuals at, ahead of `position` by the visual latency
+    pub fn visual_position(&self) -> f32 {
+        self.position + self.visual_offset
+    }
+
+    // Whether the visual position already reached the next note of `kind`, which is only
+    // reported once the song position gets there
+    pub fn visual_lead(&self, kind: NoteKind) -> bool {
+        self.neighbours(kind)
+            .is_some_and(|(_, next)| next <= self.visual_position())
+    }
+
+    // Seconds into the audio file, before loops and section changes are accounted for
+    pub fn file_position(&self) -> Option<f32> {
+        let playback = self.playback.as_ref()?;
+        Some(self.position - playback.offset)
+    }
+
+    // Completed passes through the body section
+    pub fn loops(&self) -> u32 {
+        self.playback.as_ref().map_or(0, |playback| playback.loops)
+    }
+
+    // Whether the body section will repeat once it reaches its end
+    pub fn is_looping(&self) -> bool {
+        self.playback
+            .as_ref()
+            .is_some_and(|playback| playback.section != SongSection::Outro && !playback.finishing)
+    }
+
+    // Metre of the measure at the current position
+    pub fn metre(&self) -> Option<Metre> {
+        let playback = self.playback.as_ref()?;
+        let wholes = self.tempo_map.wholes(self.position - playback.offset);
+        Some(self.tempo_map.measure(wholes)?.metre)
+    }
+
+    // Song position in seconds of the note of `kind` closest to the current position
+    pub fn nearest(&self, kind: NoteKind) -> Option<f32> {
+        let (previous, next) = self.neighbours(kind)?;
+        if self.position - previous <= next - self.position {
+            Some(previous)
+        } else {
+            Some(next)
         }
     }
 
-    fn update(&mut self, audio_info: &AudioInfo) {
-        self.timer.set_duration(Duration::from_secs_f32(
-            self.kind.length(audio_info).unwrap_or_default(),
-        ));
+    // Song positions in seconds of the notes of `kind` at or before and after the current position
+    fn neighbours(&self, kind: NoteKind) -> Option<(f32, f32)> {
+        let playback = self.playback.as_ref()?;
+        let wholes = self.tempo_map.wholes(self.position - playback.offset);
+        let measure = self.tempo_map.measure(wholes)?;
+        let length = kind.length(&measure.metre)?;
+        let previous =
+            measure.start + ((wholes - measure.start) / length + EPSILON).floor() * length;
+        let next = (previous + length).min(measure.start + measure.length);
+        let seconds = |note| self.tempo_map.seconds(note) + playback.offset;
+        Some((seconds(previous), seconds(next)))
+    }
+
+    fn measure_index(&self, wholes: f32) -> i64 {
+        self.tempo_map
+            .measure(wholes)
+            .map_or(0, |measure| measure.index as i64)
+    }
+
+    fn advance(&mut self, wholes: f32, playback: &Playback) -> Vec<BeatEvent> {
+        Self::notes(&self.tempo_map, &mut self.cursor, wholes, playback)
+    }
+
+    fn advance_clicks(&mut self, wholes: f32, playback: &Playback) -> Vec<BeatEvent> {
+        Self::notes(&self.tempo_map, &mut self.click_cursor, wholes, playback)
+    }
+
+    // Every note starting between `cursor` and `wholes`, in order. The note grid restarts on
+    // every downbeat so beat numbering stays correct across metre changes.
+    fn notes(
+        tempo_map: &TempoMap,
+        cursor: &mut f32,
+        wholes: f32,
+        playback: &Playback,
+    ) -> Vec<BeatEvent> {
+        let mut events = Vec::new();
+        let to = wholes + EPSILON;
+        while *cursor < to {
+            let Some(measure) = tempo_map.measure(*cursor) else {
+                break;
+            };
+            let end = (measure.start + measure.length).min(to);
+            if end <= *cursor {
+                break;
+            }
+            for kind in NoteKind::ALL {
+                let Some(length) = kind.length(&measure.metre) else {
+                    continue;
+                };
+                let first = ((*cursor - measure.start) / length - EPSILON)
+                    .ceil()
+                    .max(0.0);
+                let mut beat = first as u32;
+                loop {
+                    let start = measure.start + beat as f32 * length;
+                    if start >= end - EPSILON * length {
+                        break;
+                    }
+                    events.push(BeatEvent {
+                        kind,
+                        beat,
+                        measure: (measure.index as i64 + playback.measures) as u32,
+                        position: tempo_map.seconds(start) + playback.offset,
+                    });
+                    beat += 1;
+                }
+            }
+            *cursor = end;
+        }
+        events.sort_by(|a, b| a.position.total_cmp(&b.position));
+        events
     }
 }
 
 #[derive(Component, Clone, Copy, Default, Deref, DerefMut)]
-struct Tempo(f32); // BPM
+pub struct Tempo(pub f32); // BPM
 impl Tempo {}
 
-#[derive(Component, Clone, Copy, Default)]
-struct Metre {
-    top: u8,
-    bottom: u8,
+#[derive(Component, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Metre {
+    pub top: u8,
+    pub bottom: u8,
+}
+impl Metre {
+    // Compound metres (6/8, 9/8, 12/16...) group their pulses in threes and the tempo counts
+    // those dotted groups, simple and odd metres (4/4, 3/2, 7/8) count single pulses.
+    pub fn is_compound(&self) -> bool {
+        self.top > 3 && self.top.is_multiple_of(3)
+    }
+
+    // Length of the beat counted by the tempo, in whole notes
+    pub fn beat(&self) -> f32 {
+        if self.bottom == 0 {
+            return 0.0;
+        }
+        if self.is_compound() {
+            3.0 / self.bottom as f32
+        } else {
+            1.0 / self.bottom as f32
+        }
+    }
+
+    // Beats in one measure
+    pub fn beats(&self) -> f32 {
+        if self.is_compound() {
+            (self.top / 3) as f32
+        } else {
+            self.top as f32
+        }
+    }
+
+    // Length of one measure, in whole notes
+    pub fn measure(&self) -> f32 {
+        if self.bottom == 0 {
+            return 0.0;
+        }
+        self.top as f32 / self.bottom as f32
+    }
+}
+impl From<(u8, u8)> for Metre {
+    fn from((top, bottom): (u8, u8)) -> Self {
+        Self { top, bottom }
+    }
 }
-impl Metre {}
 
-#[derive(Component, Clone, Copy, Default)]
-enum NoteKind {
+#[derive(Component, Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub enum NoteKind {
     #[default]
     Whole,
     Half,
@@ -178,42 +472,179 @@ enum NoteKind {
     Eighth,
     Sixteenth,
     ThirtySecond,
+    DottedHalf,
+    DottedQuarter,
+    DottedEighth,
+    HalfTriplet,
+    QuarterTriplet,
+    EighthTriplet,
+    SixteenthTriplet,
+    Beat,
     Measure,
 }
 impl NoteKind {
-    fn length(&self, audio_info: &AudioInfo) -> Option<f32> {
+    // Measure first so it is reported ahead of the notes sharing its downbeat
+    const ALL: [NoteKind; 15] = [
+        NoteKind::Measure,
+        NoteKind::Beat,
+        NoteKind::Whole,
+        NoteKind::Half,
+        NoteKind::Quarter,
+        NoteKind::Eighth,
+        NoteKind::Sixteenth,
+        NoteKind::ThirtySecond,
+        NoteKind::DottedHalf,
+        NoteKind::DottedQuarter,
+        NoteKind::DottedEighth,
+        NoteKind::HalfTriplet,
+        NoteKind::QuarterTriplet,
+        NoteKind::EighthTriplet,
+        NoteKind::SixteenthTriplet,
+    ];
+
+    // Length in whole notes, independent of tempo. Only `Beat` and `Measure` depend on the
+    // metre, dotted notes are worth 3/2 and triplets 2/3 of their plain value.
+    fn length(&self, metre: &Metre) -> Option<f32> {
         use NoteKind::*;
-        let bps: f32 = audio_info.tempo.0 / 60.0;
-        let spb: f32 = 1.0 / bps;
-        let top: f32 = audio_info.metre.top as f32;
-        let bottom: f32 = audio_info.metre.bottom as f32;
+        if metre.measure() <= 0.0 {
+            return None;
+        }
 
         match self {
-            Whole => Some(bottom * spb),
-            Half => Some((bottom / 2.0) * spb),
-            Quarter => Some((bottom / 4.0) * spb),
-            Eighth => Some((bottom / 8.0) * spb),
-            Sixteenth => Some((bottom / 16.0) * spb),
-            ThirtySecond => Some((bottom / 32.0) * spb),
-            Measure => Some(top * spb),
+            Whole => Some(1.0),
+            Half => Some(1.0 / 2.0),
+            Quarter => Some(1.0 / 4.0),
+            Eighth => Some(1.0 / 8.0),
+            Sixteenth => Some(1.0 / 16.0),
+            ThirtySecond => Some(1.0 / 32.0),
+            DottedHalf => Some(3.0 / 4.0),
+            DottedQuarter => Some(3.0 / 8.0),
+            DottedEighth => Some(3.0 / 16.0),
+            HalfTriplet => Some(1.0 / 3.0),
+            QuarterTriplet => Some(1.0 / 6.0),
+            EighthTriplet => Some(1.0 / 12.0),
+            SixteenthTriplet => Some(1.0 / 24.0),
+            Beat => Some(metre.beat()),
+            Measure => Some(metre.measure()),
+        }
+    }
+}
+
+fn tick_metronome(
+    current_song: Res<CurrentSong>,
+    metronome_offset: Res<MetronomeOffset>,
+    music_channel: Res<AudioChannel<MusicAudioChannel>>,
+    mut query_metronome: Query<&mut Metronome>,
+    mut evw_beat: EventWriter<BeatEvent>,
+    mut evw_click: EventWriter<ClickEvent>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    let Some(song) = &current_song.0 else {
+        return;
+    };
+    let Ok(mut metronome) = query_metronome.get_single_mut() else {
+        return;
+    };
+    let Some(mut playback) = metronome.playback.clone() else {
+        return;
+    };
+    // Only the playback position reported by kira drives the beat, so there is nothing to drift
+    let playback_position = match music_channel.state(&playback.instance) {
+        PlaybackState::Playing { position } => position as f32,
+        PlaybackState::Stopped if playback.section == SongSection::Outro => {
+            evw_metronome.send(MetronomeEvent(MetronomeCommand::Stop));
+            return;
+        }
+        _ => return,
+    };
+    let info = &song.info;
+    let mut events = Vec::new();
+    let mut clicks = Vec::new();
+    if playback.section != SongSection::Outro {
+        // kira wrapped from the end of the body back to its start
+        if playback_position + *info.body / 2.0 < playback.last_position {
+            let body_end = metronome.tempo_map.wholes(info.body_end());
+            let body_start = metronome.tempo_map.wholes(info.intro_length());
+            events.extend(metronome.advance(body_end, &playback));
+            clicks.extend(metronome.advance_clicks(body_end, &playback));
+            playback.offset += *info.body;
+            playback.loops += 1;
+            playback.measures +=
+                metronome.measure_index(body_end) - metronome.measure_index(body_start);
+            metronome.seek(body_start);
+        }
+        playback.section = if playback_position < info.intro_length() {
+            SongSection::Intro
+        } else {
+            SongSection::Body
+        };
+    }
+    playback.last_position = playback_position;
+
+    let song_position = playback_position - metronome_offset.audio;
+    metronome.position = song_position + playback.offset;
+    metronome.visual_offset = metronome_offset.visual;
+    let wholes = metronome.tempo_map.wholes(song_position);
+    events.extend(metronome.advance(wholes, &playback));
+    // Clicks are mixed with the music, so they are due when the playback position reaches the
+    // note rather than once the latency has passed
+    let playback_wholes = metronome.tempo_map.wholes(playback_position);
+    clicks.extend(metronome.advance_clicks(playback_wholes, &playback));
+
+    if playback.finishing && playback.section != SongSection::Outro {
+        let downbeat =
+            |notes: &[BeatEvent]| notes.iter().position(|note| note.kind == NoteKind::Measure);
+        if info.outro.is_none() {
+            if downbeat(&events).is_some() {
+                evw_metronome.send(MetronomeEvent(MetronomeCommand::Stop));
+            }
+        } else if let Some(index) = downbeat(&clicks) {
+            // The outro replaces the measure the playback position just entered, starting as far
+            // into it as the body already played so the music carries on without a seam. Notes
+            // the song position hasn't reached yet are taken from the end of the body.
+            let downbeat = clicks[index];
+            clicks.truncate(index);
+            let downbeat_file = downbeat.position - playback.offset;
+            let shift = info.body_end() - downbeat_file;
+            let start = info.body_end() + (playback_position - downbeat_file).max(0.0);
+            // Queued before the outro, so it only stops the body
+            music_channel.stop();
+            playback.instance = music_channel
+                .play(song.handle.clone())
+                .start_from(start as f64)
+                .handle();
+            let outro_start = metronome.tempo_map.wholes(info.body_end());
+            let cursor = metronome.tempo_map.seconds(metronome.cursor) + shift;
+            playback.offset -= shift;
+            playback.measures = downbeat.measure as i64 - metronome.measure_index(outro_start);
+            playback.last_position = start;
+            playback.section = SongSection::Outro;
+            metronome.seek(outro_start);
+            metronome.cursor = metronome.tempo_map.wholes(cursor);
+            info!("[OUTRO] Metronome");
         }
     }
+    evw_beat.send_batch(events);
+    evw_click.send_batch(clicks.into_iter().map(ClickEvent));
+    metronome.playback = Some(playback);
 }
 
-fn tick_metronome(time: Res<Time>, mut query_metronome: Query<&mut Metronome>) {
-    if let Ok(mut metronome) = query_metronome.get_single_mut() {
-        metronome.whole_note.timer.tick(time.delta());
-        metronome.half_note.timer.tick(time.delta());
-        metronome.quarter_note.timer.tick(time.delta());
-        metronome.eighth_note.timer.tick(time.delta());
-        metronome.sixteenth_note.timer.tick(time.delta());
-        metronome.thirtysecond_note.timer.tick(time.delta());
+pub fn load_current_song(
+    audio_assets: Res<AudioAssets>,
+    songs: Res<Assets<SongAsset>>,
+    mut current_song: ResMut<CurrentSong>,
+) {
+    if let Some(song) = songs.get(&audio_assets.demo) {
+        current_song.0 = Some(Song::from(song));
+        info!("[LOADED] Song: {}", song.title);
     }
 }
 
 fn evr_control_metronome(
     mut evr_control_metronome: EventReader<MetronomeEvent>,
-    metronome_channel: Res<AudioChannel<MetronomeAudioChannel>>,
+    mut current_song: ResMut<CurrentSong>,
+    mut query_metronome: Query<&mut Metronome>,
+    music_channel: Res<AudioChannel<MusicAudioChannel>>,
     metronome_state: Res<State<MetronomeState>>,
     mut next_metronome_state: ResMut<NextState<MetronomeState>>,
 ) {
@@ -221,24 +652,194 @@ fn evr_control_metronome(
     for ev in evr_control_metronome.read() {
         match &ev.0 {
             Play(song) => {
-                metronome_channel.play(song.handle.clone());
+                music_channel.stop();
+                let mut command = music_channel.play(song.handle.clone());
+                // The body loops seamlessly inside kira until a Finish command arrives
+                if *song.info.body > 0.0 {
+                    command
+                        .loop_from(song.info.intro_length() as f64)
+                        .loop_until(song.info.body_end() as f64);
+                }
+                let instance = command.handle();
+                if let Ok(mut metronome) = query_metronome.get_single_mut() {
+                    metronome.playback = Some(Playback::new(instance));
+                }
+                current_song.0 = Some(song.clone());
+            }
+            Pause => {
+                if metronome_state.get() != &MetronomeState::Playing {
+                    continue;
+                }
+                music_channel.pause().linear_fade_out(PAUSE_FADE);
+            }
+            Resume => {
+                if metronome_state.get() != &MetronomeState::Paused {
+                    continue;
+                }
+                music_channel.resume().linear_fade_in(PAUSE_FADE);
+            }
+            Finish => {
+                if let Ok(mut metronome) = query_metronome.get_single_mut() {
+                    if let Some(playback) = &mut metronome.playback {
+                        playback.finishing = true;
+                    }
+                }
+            }
+            Stop => {
+                music_channel.stop();
+                if let Ok(mut metronome) = query_metronome.get_single_mut() {
+                    metronome.playback = None;
+                    metronome.seek(0.0);
+                }
             }
-            Pause => {}
-            Resume => {}
-            Stop => {}
         }
-        if metronome_state.get() != &ev.0.state() {
-            next_metronome_state.set(ev.0.state());
+        if let Some(state) = ev.0.state() {
+            if metronome_state.get() != &state {
+                next_metronome_state.set(state);
+            }
         }
     }
 }
 
-fn update_note_timers(current_song: Res<CurrentSong>, mut query_metronome: Query<&mut Metronome>) {
+fn update_tempo_map(current_song: Res<CurrentSong>, mut query_metronome: Query<&mut Metronome>) {
     if current_song.is_changed() {
         if let Some(song) = &current_song.0 {
             if let Ok(mut metronome) = query_metronome.get_single_mut() {
-                metronome.update(&song.info);
+                metronome.update(song);
             }
         }
     }
 }
+
+fn apply_volume(
+    settings: Res<Settings>,
+    music_duck: Res<MusicDuck>,
+    music_channel: Res<AudioChannel<MusicAudioChannel>>,
+    sfx_channel: Res<AudioChannel<SfxAudioChannel>>,
+    click_channel: Res<AudioChannel<ClickAudioChannel>>,
+    ui_channel: Res<AudioChannel<UiAudioChannel>>,
+) {
+    let volume = &settings.volume;
+    music_channel.set_volume((volume.gain(AudioBus::Music) * **music_duck) as f64);
+    sfx_channel.set_volume(volume.gain(AudioBus::Sfx) as f64);
+    click_channel.set_volume(volume.gain(AudioBus::Click) as f64);
+    ui_channel.set_volume(volume.gain(AudioBus::Ui) as f64);
+}
+
+fn evr_play_clicks(
+    settings: Res<Settings>,
+    audio_assets: Res<AudioAssets>,
+    click_channel: Res<AudioChannel<ClickAudioChannel>>,
+    mut evr_click: EventReader<ClickEvent>,
+) {
+    let click = settings.click;
+    for ev in evr_click.read() {
+        if !click.enabled {
+            continue;
+        }
+        // The downbeat is accented instead of clicked twice
+        if ev.kind == NoteKind::Measure {
+            click_channel.play(audio_assets.click_accent.clone());
+        } else if ev.kind == click.subdivision.kind() && ev.beat != 0 {
+            click_channel.play(audio_assets.click.clone());
+        }
+    }
+}
+
+fn pause_metronome(
+    metronome_state: Res<State<MetronomeState>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    if metronome_state.get() == &MetronomeState::Playing {
+        evw_metronome.send(MetronomeEvent(MetronomeCommand::Pause));
+        info!("[PAUSED] Metronome");
+    }
+}
+
+fn resume_metronome(
+    metronome_state: Res<State<MetronomeState>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    if metronome_state.get() == &MetronomeState::Paused {
+        evw_metronome.send(MetronomeEvent(MetronomeCommand::Resume));
+        info!("[RESUMED] Metronome");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(kind: NoteKind, tempo: f32, metre: (u8, u8)) -> f32 {
+        let info = AudioInfo {
+            tempo: Tempo(tempo),
+            metre: Metre::from(metre),
+            ..default()
+        };
+        let length = kind.length(&info.metre).unwrap();
+        TempoMap::new(&info, &[]).seconds(length)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn simple_metre_counts_the_denominator() {
+        assert_close(seconds(NoteKind::Beat, 120.0, (4, 4)), 0.5);
+        assert_close(seconds(NoteKind::Quarter, 120.0, (4, 4)), 0.5);
+        assert_close(seconds(NoteKind::Whole, 120.0, (4, 4)), 2.0);
+        assert_close(seconds(NoteKind::Measure, 120.0, (4, 4)), 2.0);
+        assert_close(seconds(NoteKind::Measure, 90.0, (3, 4)), 2.0);
+    }
+
+    #[test]
+    fn half_note_beat_in_three_two() {
+        assert_eq!(Metre::from((3, 2)).beats(), 3.0);
+        assert_close(seconds(NoteKind::Beat, 60.0, (3, 2)), 1.0);
+        assert_close(seconds(NoteKind::Half, 60.0, (3, 2)), 1.0);
+        assert_close(seconds(NoteKind::Quarter, 60.0, (3, 2)), 0.5);
+        assert_close(seconds(NoteKind::Measure, 60.0, (3, 2)), 3.0);
+    }
+
+    #[test]
+    fn compound_metre_counts_dotted_beats() {
+        let metre = Metre::from((6, 8));
+        assert!(metre.is_compound());
+        assert_eq!(metre.beats(), 2.0);
+        assert_close(seconds(NoteKind::Beat, 60.0, (6, 8)), 1.0);
+        assert_close(seconds(NoteKind::DottedQuarter, 60.0, (6, 8)), 1.0);
+        assert_close(seconds(NoteKind::Eighth, 60.0, (6, 8)), 1.0 / 3.0);
+        assert_close(seconds(NoteKind::Measure, 60.0, (6, 8)), 2.0);
+        assert_close(seconds(NoteKind::Measure, 60.0, (12, 8)), 4.0);
+    }
+
+    #[test]
+    fn odd_metres_are_not_compound() {
+        for metre in [(3, 4), (3, 8), (5, 4), (7, 8)] {
+            assert!(!Metre::from(metre).is_compound());
+        }
+        assert_close(seconds(NoteKind::Beat, 120.0, (7, 8)), 0.5);
+        assert_close(seconds(NoteKind::Measure, 120.0, (7, 8)), 3.5);
+    }
+
+    #[test]
+    fn dotted_and_triplet_subdivisions() {
+        assert_close(seconds(NoteKind::DottedHalf, 60.0, (4, 4)), 3.0);
+        assert_close(seconds(NoteKind::DottedQuarter, 60.0, (4, 4)), 1.5);
+        assert_close(seconds(NoteKind::DottedEighth, 60.0, (4, 4)), 0.75);
+        assert_close(seconds(NoteKind::HalfTriplet, 60.0, (4, 4)), 4.0 / 3.0);
+        assert_close(seconds(NoteKind::QuarterTriplet, 60.0, (4, 4)), 2.0 / 3.0);
+        assert_close(seconds(NoteKind::EighthTriplet, 60.0, (4, 4)), 1.0 / 3.0);
+        assert_close(seconds(NoteKind::SixteenthTriplet, 60.0, (4, 4)), 1.0 / 6.0);
+    }
+
+    #[test]
+    fn invalid_metre_has_no_length() {
+        assert_eq!(NoteKind::Quarter.length(&Metre::from((4, 0))), None);
+        assert_eq!(NoteKind::Measure.length(&Metre::default()), None);
+    }
+}
@@ -0,0 +1,261 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::actions::{ActionBinding, NavigationAction, UiButtonAction};
+use crate::audio::{
+    load_current_song, BeatEvent, MetronomeCommand, MetronomeEvent, MetronomeOffset, MusicDuck,
+    NoteKind, Song,
+};
+use crate::judgement::JudgementEvent;
+use crate::loading::{AudioAssets, UiAssets};
+use crate::settings::{Latency, Settings};
+use crate::song::SongAsset;
+use crate::ui::{Palette, UiTextColor};
+use crate::SettingsState;
+
+pub struct CalibrationPlugin;
+impl Plugin for CalibrationPlugin {
+    fn name(&self) -> &str {
+        "Calibration Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(SettingsState::Calibration), on_enter)
+            .add_systems(
+                Update,
+                (evr_record_taps, evr_flash_on_beat, update_calibration_text)
+                    .chain()
+                    .run_if(in_state(SettingsState::Calibration)),
+            )
+            .add_systems(
+                OnExit(SettingsState::Calibration),
+                (cleanup, load_current_song),
+            );
+    }
+}
+
+// DATA
+
+// Taps ignored while the player finds the beat, then taps averaged for each phase
+const WARMUP_TAPS: usize = 4;
+const SAMPLE_TAPS: usize = 12;
+// Milliseconds, taps further from the beat than this are treated as stray presses
+const MAX_TAP_OFFSET: f32 = 250.0;
+const FLASH_SECONDS: f32 = 0.1;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum CalibrationPhase {
+    // Tap along to the click track
+    #[default]
+    Audio,
+    // Tap along to the flash with the click track muted
+    Visual,
+}
+
+#[derive(Resource, Default)]
+pub struct Calibration {
+    pub phase: CalibrationPhase,
+    pub taps: usize,
+    pub offsets: Vec<f32>,
+    pub audio: Option<f32>,
+}
+
+#[derive(Component)]
+struct CalibrationText;
+
+#[derive(Component, Default)]
+struct CalibrationFlash(Timer);
+
+#[derive(Component)]
+struct CleanupCalibration;
+
+// Mean of the middle half of the offsets, so a few mistimed taps don't skew the result
+fn average(offsets: &[f32]) -> f32 {
+    let mut sorted = offsets.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let quarter = sorted.len() / 4;
+    let middle = &sorted[quarter..sorted.len() - quarter];
+    if middle.is_empty() {
+        return 0.0;
+    }
+    middle.iter().sum::<f32>() / middle.len() as f32
+}
+
+// SYSTEMS
+
+fn on_enter(
+    mut commands: Commands,
+    ui: Res<UiAssets>,
+    audio_assets: Res<AudioAssets>,
+    songs: Res<Assets<SongAsset>>,
+    mut metronome_offset: ResMut<MetronomeOffset>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    // Taps are measured against the raw playback position
+    *metronome_offset = MetronomeOffset::default();
+    if let Some(song) = songs.get(&audio_assets.calibration) {
+        evw_metronome.send(MetronomeEvent(MetronomeCommand::Play(Song::from(song))));
+    }
+    commands.insert_resource(Calibration::default());
+
+    let text_font = TextFont {
+        font: ui.pixelify.clone(),
+        font_size: 8.0,
+        ..default()
+    };
+    commands
+        .spawn((
+            Name::new("Calibration Parent Node"),
+            Node {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::SpaceEvenly,
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            CleanupCalibration,
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Name::new("Calibration Text Node"),
+                Text::new(""),
+                text_font,
+                TextColor(UiTextColor::default().normal.srgb()),
+                CalibrationText,
+            ));
+            children.spawn((
+                Name::new("Calibration Flash Node"),
+                Node {
+                    width: Val::Px(28.),
+                    height: Val::Px(28.),
+                    ..default()
+                },
+                BackgroundColor(Palette::White.srgb()),
+                Visibility::Hidden,
+                CalibrationFlash::default(),
+            ));
+        });
+    info!("[SPAWNED] Calibration");
+}
+
+fn evr_record_taps(
+    mut calibration: ResMut<Calibration>,
+    mut settings: ResMut<Settings>,
+    mut music_duck: ResMut<MusicDuck>,
+    query_button_action: Query<&ActionState<UiButtonAction>>,
+    query_navigation: Query<&ActionState<NavigationAction>>,
+    mut settings_state: ResMut<NextState<SettingsState>>,
+    mut evr_judgement: EventReader<JudgementEvent>,
+) {
+    let cancelled = query_button_action
+        .iter()
+        .any(|action_state| action_state.just_pressed(&UiButtonAction::Four))
+        || query_navigation
+            .iter()
+            .any(|action_state| action_state.just_pressed(&NavigationAction::Back));
+    if cancelled {
+        info!("[CANCELLED] Calibration");
+        settings_state.set(SettingsState::Main);
+        return;
+    }
+    for ev in evr_judgement.read() {
+        if ev.action != UiButtonAction::One {
+            continue;
+        }
+        calibration.taps += 1;
+        if calibration.taps <= WARMUP_TAPS || ev.offset.abs() > MAX_TAP_OFFSET {
+            continue;
+        }
+        calibration.offsets.push(ev.offset);
+        if calibration.offsets.len() < SAMPLE_TAPS {
+            continue;
+        }
+
+        let offset = average(&calibration.offsets);
+        calibration.offsets.clear();
+        calibration.taps = 0;
+        match calibration.phase {
+            CalibrationPhase::Audio => {
+                calibration.audio = Some(offset);
+                calibration.phase = CalibrationPhase::Visual;
+                **music_duck = 0.0;
+            }
+            CalibrationPhase::Visual => {
+                settings.latency = Latency {
+                    audio: calibration.audio.unwrap_or_default(),
+                    visual: offset,
+                };
+                info!("[CALIBRATED] Latency: {:?}", settings.latency);
+                settings_state.set(SettingsState::Main);
+                return;
+            }
+        }
+    }
+}
+
+fn evr_flash_on_beat(
+    time: Res<Time>,
+    calibration: Res<Calibration>,
+    mut query_flash: Query<(&mut CalibrationFlash, &mut Visibility)>,
+    mut evr_beat: EventReader<BeatEvent>,
+) {
+    let beat = evr_beat.read().any(|ev| ev.kind == NoteKind::Beat);
+    for (mut flash, mut visibility) in &mut query_flash {
+        if beat && calibration.phase == CalibrationPhase::Visual {
+            flash.0 = Timer::from_seconds(FLASH_SECONDS, TimerMode::Once);
+        } else {
+            flash.0.tick(time.delta());
+        }
+        visibility.set_if_neq(if flash.0.finished() {
+            Visibility::Hidden
+        } else {
+            Visibility::Visible
+        });
+    }
+}
+
+fn update_calibration_text(
+    calibration: Res<Calibration>,
+    settings: Res<Settings>,
+    mut query_text: Query<&mut Text, With<CalibrationText>>,
+) {
+    if !calibration.is_changed() {
+        return;
+    }
+    let label = |action| {
+        settings
+            .bindings
+            .get(action)
+            .map_or(format!("{action:?}"), ActionBinding::label)
+    };
+    let (tap, cancel) = (label(UiButtonAction::One), label(UiButtonAction::Four));
+    let cue = match calibration.phase {
+        CalibrationPhase::Audio => "click",
+        CalibrationPhase::Visual => "flash",
+    };
+    let progress = calibration.offsets.len();
+    for mut text in &mut query_text {
+        text.0 =
+            format!("Press {tap} on every {cue}\n{progress}/{SAMPLE_TAPS}\n{cancel} to cancel");
+    }
+}
+
+fn cleanup(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    mut music_duck: ResMut<MusicDuck>,
+    query_cleanup: Query<Entity, With<CleanupCalibration>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    evw_metronome.send(MetronomeEvent(MetronomeCommand::Stop));
+    **music_duck = 1.0;
+    // Puts the saved latency back, even when cancelled
+    settings.set_changed();
+    commands.remove_resource::<Calibration>();
+    for entity in query_cleanup.iter() {
+        commands.entity(entity).despawn_recursive();
+        info!("[CLEANUP] Calibration");
+    }
+}
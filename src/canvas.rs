@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+use bevy::render::camera::{RenderTarget, Viewport};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::view::RenderLayers;
+use bevy::window::PrimaryWindow;
+
+use crate::settings::Settings;
+use crate::ui::Palette;
+
+pub struct CanvasPlugin;
+impl Plugin for CanvasPlugin {
+    fn name(&self) -> &str {
+        "Canvas Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, startup).add_systems(
+            Update,
+            (
+                resize_canvas.run_if(resource_changed::<Settings>),
+                fit_canvas,
+            )
+                .chain(),
+        );
+    }
+}
+
+// DATA
+
+// The world is drawn at the native resolution `size` into an image, which is shown on the window
+// scaled by the largest integer `scale` that fits, with black bars around it. The UI is laid out
+// in the same native pixels and drawn on top at the same scale through `UiScale`, but by the
+// window camera rather than into the image, so text and borders aren't locked to the native pixel
+// grid the way the world is. Bevy only reports `Interaction` for UI on window cameras.
+#[derive(Resource)]
+pub struct Canvas {
+    pub size: UVec2,
+    pub scale: u32,
+}
+
+// Layer of the upscaled canvas sprite, everything else stays on the default layer 0
+const CANVAS_LAYER: usize = 1;
+
+#[derive(Component)]
+struct CanvasSprite;
+
+#[derive(Component)]
+struct CanvasCamera;
+
+// SYSTEMS
+
+fn startup(mut commands: Commands, settings: Res<Settings>, mut images: ResMut<Assets<Image>>) {
+    let native = settings.resolution.vec.max(Vec2::ONE).as_uvec2();
+    let size = Extent3d {
+        width: native.x,
+        height: native.y,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    let image = images.add(image);
+
+    commands.spawn((
+        Name::new("Game Camera"),
+        Camera2d,
+        Camera {
+            target: RenderTarget::Image(image.clone()),
+            order: -1,
+            ..default()
+        },
+        Msaa::Off,
+    ));
+    commands.spawn((
+        Name::new("Letterbox Camera"),
+        Camera2d,
+        Camera {
+            clear_color: ClearColorConfig::Custom(Palette::Black.srgb()),
+            ..default()
+        },
+        RenderLayers::none(),
+        Msaa::Off,
+    ));
+    commands.spawn((
+        Name::new("Canvas Camera"),
+        Camera2d,
+        Camera {
+            order: 1,
+            clear_color: ClearColorConfig::None,
+            ..default()
+        },
+        RenderLayers::layer(CANVAS_LAYER),
+        IsDefaultUiCamera,
+        Msaa::Off,
+        CanvasCamera,
+    ));
+    commands.spawn((
+        Name::new("Canvas Sprite"),
+        Sprite::from_image(image),
+        RenderLayers::layer(CANVAS_LAYER),
+        CanvasSprite,
+    ));
+    commands.insert_resource(Canvas {
+        size: native,
+        scale: 1,
+    });
+    info!("[SPAWNED] Canvas: {}x{}", native.x, native.y);
+}
+
+// Resizes the image when the native resolution in the settings changes
+fn resize_canvas(
+    mut canvas: ResMut<Canvas>,
+    settings: Res<Settings>,
+    mut images: ResMut<Assets<Image>>,
+    query_sprite: Query<&Sprite, With<CanvasSprite>>,
+) {
+    let native = settings.resolution.vec.max(Vec2::ONE).as_uvec2();
+    if canvas.size == native {
+        return;
+    }
+    for sprite in &query_sprite {
+        if let Some(image) = images.get_mut(&sprite.image) {
+            image.resize(Extent3d {
+                width: native.x,
+                height: native.y,
+                depth_or_array_layers: 1,
+            });
+        }
+    }
+    canvas.size = native;
+    info!("[RESIZED] Canvas: {}x{}", native.x, native.y);
+}
+
+// Keeps the canvas at the largest integer scale of its native size in physical pixels
+fn fit_canvas(
+    mut canvas: ResMut<Canvas>,
+    mut ui_scale: ResMut<UiScale>,
+    query_window: Query<Ref<Window>, With<PrimaryWindow>>,
+    mut query_camera: Query<&mut Camera, With<CanvasCamera>>,
+    mut query_sprite: Query<&mut Transform, With<CanvasSprite>>,
+) {
+    let Ok(window) = query_window.get_single() else {
+        return;
+    };
+    if !window.is_changed() && !canvas.is_changed() {
+        return;
+    }
+    let physical = window.physical_size();
+    let scale = (physical / canvas.size).min_element().max(1);
+    let size = (canvas.size * scale).min(physical);
+    let viewport = Viewport {
+        physical_position: (physical - size) / 2,
+        physical_size: size,
+        ..default()
+    };
+    for mut camera in &mut query_camera {
+        let current = camera
+            .viewport
+            .as_ref()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size));
+        if current != Some((viewport.physical_position, viewport.physical_size)) {
+            camera.viewport = Some(viewport.clone());
+        }
+    }
+
+    // Logical pixels per native pixel
+    let logical_scale = scale as f32 / window.scale_factor();
+    for mut transform in &mut query_sprite {
+        transform.scale = Vec3::new(logical_scale, logical_scale, 1.);
+    }
+    if ui_scale.0 != logical_scale {
+        ui_scale.0 = logical_scale;
+    }
+    if canvas.scale != scale {
+        canvas.scale = scale;
+        info!("[RESIZED] Canvas: {scale}x");
+    }
+}
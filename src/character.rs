@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use serde::Deserialize;
 
 pub struct CharacterPlugin;
 impl Plugin for CharacterPlugin {
@@ -7,18 +8,25 @@ impl Plugin for CharacterPlugin {
     }
 
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup);
+        app.add_systems(Startup, startup)
+            .add_systems(PostUpdate, derive_stats);
     }
 }
 
 // DATA
 
-#[derive(Component)]
+// Level every stat starts at
+const BASE_LEVEL: u8 = 1;
+// Timing windows never grow past this multiple of their default width
+const MAX_LENIENCY: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CharacterStat {
     pub kind: StatKind,
     pub level: u8,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
 pub enum StatKind {
     Constitution,
     Agility,
@@ -26,6 +34,169 @@ pub enum StatKind {
     Nature,
     Social,
 }
+impl StatKind {
+    pub const ALL: [StatKind; 5] = [
+        StatKind::Constitution,
+        StatKind::Agility,
+        StatKind::Occult,
+        StatKind::Nature,
+        StatKind::Social,
+    ];
+}
+
+// Level of every `StatKind`, `DerivedStats` are recomputed from it whenever it changes
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct StatSheet(pub Vec<CharacterStat>);
+impl Default for StatSheet {
+    fn default() -> Self {
+        Self(
+            StatKind::ALL
+                .into_iter()
+                .map(|kind| CharacterStat {
+                    kind,
+                    level: BASE_LEVEL,
+                })
+                .collect(),
+        )
+    }
+}
+impl StatSheet {
+    pub fn level(&self, kind: StatKind) -> u8 {
+        self.0
+            .iter()
+            .find(|stat| stat.kind == kind)
+            .map_or(0, |stat| stat.level)
+    }
+
+    pub fn set(&mut self, kind: StatKind, level: u8) {
+        match self.0.iter_mut().find(|stat| stat.kind == kind) {
+            Some(stat) => stat.level = level,
+            None => self.0.push(CharacterStat { kind, level }),
+        }
+    }
+}
+// Stats that aren't listed stay at the base level
+impl FromIterator<(StatKind, u8)> for StatSheet {
+    fn from_iter<I: IntoIterator<Item = (StatKind, u8)>>(iter: I) -> Self {
+        let mut sheet = Self::default();
+        for (kind, level) in iter {
+            sheet.set(kind, level);
+        }
+        sheet
+    }
+}
+
+// Attributes combat reads, worked out from a `StatSheet`. `leniency` widens the timing
+// windows, `spell_power` scales skills, `healing` scales items and `luck` scales drop chances.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct DerivedStats {
+    pub max_health: u32,
+    pub attack: u32,
+    pub defense: u32,
+    pub leniency: f32,
+    pub spell_power: f32,
+    pub healing: f32,
+    pub luck: f32,
+}
+impl From<&StatSheet> for DerivedStats {
+    fn from(sheet: &StatSheet) -> Self {
+        let level = |kind| sheet.level(kind) as u32;
+        let constitution = level(StatKind::Constitution);
+        let agility = level(StatKind::Agility);
+        Self {
+            max_health: 20 + 5 * constitution,
+            attack: 4 + (constitution + agility) / 2,
+            defense: constitution / 2,
+            leniency: (1.0 + 0.05 * agility as f32).min(MAX_LENIENCY),
+            spell_power: 1.0 + 0.1 * level(StatKind::Occult) as f32,
+            healing: 1.0 + 0.1 * level(StatKind::Nature) as f32,
+            luck: 1.0 + 0.05 * level(StatKind::Social) as f32,
+        }
+    }
+}
 
 // SYSTEMS
 fn startup() {}
+
+fn derive_stats(
+    mut commands: Commands,
+    query_stat_sheet: Query<(Entity, &StatSheet), Changed<StatSheet>>,
+) {
+    for (entity, sheet) in &query_stat_sheet {
+        commands.entity(entity).insert(DerivedStats::from(sheet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derived(levels: &[(StatKind, u8)]) -> DerivedStats {
+        DerivedStats::from(&levels.iter().copied().collect::<StatSheet>())
+    }
+
+    #[test]
+    fn default_sheet_is_base_level() {
+        let sheet = StatSheet::default();
+        for kind in StatKind::ALL {
+            assert_eq!(sheet.level(kind), BASE_LEVEL);
+        }
+    }
+
+    #[test]
+    fn unlisted_stats_stay_at_base_level() {
+        let sheet: StatSheet = [(StatKind::Occult, 4)].into_iter().collect();
+        assert_eq!(sheet.level(StatKind::Occult), 4);
+        assert_eq!(sheet.level(StatKind::Agility), BASE_LEVEL);
+        assert_eq!(sheet.0.len(), StatKind::ALL.len());
+    }
+
+    #[test]
+    fn constitution_raises_health_and_defense() {
+        let base = derived(&[]);
+        assert_eq!(base.max_health, 25);
+        assert_eq!(base.defense, 0);
+        let sturdy = derived(&[(StatKind::Constitution, 4)]);
+        assert_eq!(sturdy.max_health, 40);
+        assert_eq!(sturdy.defense, 2);
+    }
+
+    #[test]
+    fn attack_averages_constitution_and_agility() {
+        assert_eq!(derived(&[]).attack, 5);
+        let attack = derived(&[(StatKind::Constitution, 3), (StatKind::Agility, 5)]).attack;
+        assert_eq!(attack, 8);
+    }
+
+    #[test]
+    fn agility_leniency_is_capped() {
+        assert!((derived(&[]).leniency - 1.05).abs() < 1e-6);
+        assert!((derived(&[(StatKind::Agility, 4)]).leniency - 1.2).abs() < 1e-6);
+        assert_eq!(
+            derived(&[(StatKind::Agility, u8::MAX)]).leniency,
+            MAX_LENIENCY
+        );
+    }
+
+    #[test]
+    fn occult_nature_and_social_scale_linearly() {
+        let stats = derived(&[
+            (StatKind::Occult, 5),
+            (StatKind::Nature, 3),
+            (StatKind::Social, 2),
+        ]);
+        assert!((stats.spell_power - 1.5).abs() < 1e-6);
+        assert!((stats.healing - 1.3).abs() < 1e-6);
+        assert!((stats.luck - 1.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_levels_give_the_floor() {
+        let stats = derived(&StatKind::ALL.map(|kind| (kind, 0)));
+        assert_eq!(stats.max_health, 20);
+        assert_eq!(stats.attack, 4);
+        assert_eq!(stats.defense, 0);
+        assert_eq!(stats.leniency, 1.0);
+        assert_eq!(stats.spell_power, 1.0);
+    }
+}
@@ -0,0 +1,410 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{ron, AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use serde::Deserialize;
+
+use crate::actions::UiButtonAction;
+use crate::audio::{AudioInfo, CurrentSong, Metronome, MetronomeSet};
+use crate::loader::RonLoaderError;
+use crate::tempo::TempoMap;
+
+pub struct ChartPlugin;
+impl Plugin for ChartPlugin {
+    fn name(&self) -> &str {
+        "Chart Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (build_schedule, schedule_notes).chain().after(MetronomeSet),
+        )
+        .init_asset::<ChartAsset>()
+        .init_asset_loader::<ChartLoader>()
+        .init_resource::<ChartSchedule>();
+    }
+}
+
+// DATA
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+// A `.chart` file is a RON list of notes, e.g.
+// [
+//     (measure: 1, beat: 0, lane: One),
+//     (measure: 1, beat: 1, subdivision: (1, 2), lane: Two),
+//     (measure: 1, beat: 2, lane: Three, kind: Hold(beats: 2.0)),
+//     (measure: 2, beat: 0, lane: One, kind: Double(lane: Four)),
+// ]
+// Measures count from the start of the audio file, `subdivision` is a fraction of a beat.
+#[derive(Asset, TypePath, Deref)]
+pub struct ChartAsset(pub Vec<ChartNote>);
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct ChartNote {
+    pub measure: u32,
+    pub beat: u32,
+    #[serde(default = "ChartNote::on_the_beat")]
+    pub subdivision: (u32, u32),
+    pub lane: UiButtonAction,
+    #[serde(default)]
+    pub kind: ChartNoteKind,
+}
+impl ChartNote {
+    fn on_the_beat() -> (u32, u32) {
+        (0, 1)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize)]
+pub enum ChartNoteKind {
+    #[default]
+    Tap,
+    Hold {
+        beats: f32,
+    },
+    // Hit together with a second lane
+    Double {
+        lane: UiButtonAction,
+    },
+}
+
+// A note of the chart timed in seconds of the audio file
+#[derive(Clone, Copy, Debug)]
+struct TimedNote {
+    lane: UiButtonAction,
+    kind: ChartNoteKind,
+    start: f32,
+    end: f32,
+}
+
+// Identifies one occurrence of a chart note, as notes in the body come back on every loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NoteId {
+    pub index: usize,
+    pub pass: u32,
+}
+
+// A chart note placed on the song position timeline of the `Metronome`
+#[derive(Clone, Copy, Debug)]
+pub struct ScheduledNote {
+    pub id: NoteId,
+    pub lane: UiButtonAction,
+    pub kind: ChartNoteKind,
+    pub position: f32,
+    pub end: f32,
+}
+
+#[derive(Resource)]
+pub struct ChartSchedule {
+    pub difficulty: Difficulty,
+    // Seconds ahead of and behind the current position kept in `upcoming`
+    pub lookahead: f32,
+    pub behind: f32,
+    pub upcoming: Vec<ScheduledNote>,
+    pub judged: HashSet<NoteId>,
+    // Hold notes hit on time on each lane, judged again when the lane is released
+    pub holding: HashMap<UiButtonAction, ScheduledNote>,
+    chart: Option<AssetId<ChartAsset>>,
+    notes: Vec<TimedNote>,
+}
+impl Default for ChartSchedule {
+    fn default() -> Self {
+        Self {
+            difficulty: Difficulty::default(),
+            lookahead: 2.0,
+            behind: 0.25,
+            upcoming: Vec::new(),
+            judged: HashSet::new(),
+            holding: HashMap::new(),
+            chart: None,
+            notes: Vec::new(),
+        }
+    }
+}
+impl ChartSchedule {
+    pub fn is_active(&self) -> bool {
+        !self.notes.is_empty()
+    }
+
+    // Closest note on `lane` that has not been judged yet
+    pub fn nearest(&self, lane: UiButtonAction, position: f32) -> Option<&ScheduledNote> {
+        self.upcoming
+            .iter()
+            .filter(|note| note.lane == lane && !self.judged.contains(&note.id))
+            .min_by(|a, b| {
+                (a.position - position)
+                    .abs()
+                    .total_cmp(&(b.position - position).abs())
+            })
+    }
+}
+
+#[derive(Default)]
+pub struct ChartLoader;
+impl AssetLoader for ChartLoader {
+    type Asset = ChartAsset;
+    type Settings = ();
+    type Error = RonLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let notes: Vec<ChartNote> = ron::de::from_bytes(&bytes)?;
+        if notes.iter().any(|note| note.subdivision.1 == 0) {
+            return Err(RonLoaderError::Invalid("subdivision can't divide by zero"));
+        }
+        if notes
+            .iter()
+            .any(|note| matches!(note.kind, ChartNoteKind::Hold { beats } if beats <= 0.0))
+        {
+            return Err(RonLoaderError::Invalid(
+                "holds must last longer than zero beats",
+            ));
+        }
+        Ok(ChartAsset(notes))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["chart"]
+    }
+}
+
+// Every note of `chart` timed in seconds of the audio file, in order. Double notes become one
+// note per lane.
+fn timed_notes(chart: &ChartAsset, tempo_map: &TempoMap) -> Vec<TimedNote> {
+    let mut timed = Vec::new();
+    for note in chart.iter() {
+        let Some(measure) = tempo_map.measure_at_index(note.measure) else {
+            continue;
+        };
+        let (numerator, denominator) = note.subdivision;
+        let beats = note.beat as f32 + numerator as f32 / denominator as f32;
+        let start = measure.start + beats * measure.metre.beat();
+        let end = match note.kind {
+            ChartNoteKind::Hold { beats: length } => start + length * measure.metre.beat(),
+            _ => start,
+        };
+        let mut lanes = vec![note.lane];
+        if let ChartNoteKind::Double { lane } = note.kind {
+            lanes.push(lane);
+        }
+        for lane in lanes {
+            timed.push(TimedNote {
+                lane,
+                kind: note.kind,
+                start: tempo_map.seconds(start),
+                end: tempo_map.seconds(end),
+            });
+        }
+    }
+    timed.sort_by(|a, b| a.start.total_cmp(&b.start));
+    timed
+}
+
+// File positions and loop passes a note starting at `start` can be seen at. Body notes are also
+// seen one pass ahead near the end of the body while it loops, and one pass behind just after it
+// wrapped.
+fn passes(start: f32, info: &AudioInfo, loops: u32, looping: bool) -> Vec<(f32, u32)> {
+    let body = info.body_end() - info.intro_length();
+    let mut passes = vec![(start, loops)];
+    if start >= info.intro_length() && start < info.body_end() && body > 0.0 {
+        if looping {
+            passes.push((start + body, loops + 1));
+        }
+        if loops > 0 {
+            passes.push((start - body, loops - 1));
+        }
+    }
+    passes
+}
+
+// SYSTEMS
+
+fn build_schedule(
+    current_song: Res<CurrentSong>,
+    charts: Res<Assets<ChartAsset>>,
+    mut schedule: ResMut<ChartSchedule>,
+) {
+    let Some(song) = &current_song.0 else {
+        return;
+    };
+    let chart = song.chart(schedule.difficulty).map(|handle| handle.id());
+    if !current_song.is_changed() && chart == schedule.chart {
+        return;
+    }
+    // Not loaded yet (or no chart for this difficulty), try again next frame
+    let Some(notes) = chart.and_then(|id| charts.get(id)) else {
+        schedule.chart = None;
+        schedule.notes.clear();
+        return;
+    };
+
+    schedule.chart = chart;
+    schedule.notes = timed_notes(notes, &song.tempo_map());
+    schedule.upcoming.clear();
+    schedule.judged.clear();
+    schedule.holding.clear();
+    info!("[SCHEDULED] Chart: {} notes", schedule.notes.len());
+}
+
+fn schedule_notes(
+    current_song: Res<CurrentSong>,
+    query_metronome: Query<&Metronome>,
+    mut schedule: ResMut<ChartSchedule>,
+) {
+    let (Some(song), Ok(metronome)) = (&current_song.0, query_metronome.get_single()) else {
+        return;
+    };
+    let Some(file_position) = metronome.file_position() else {
+        schedule.upcoming.clear();
+        return;
+    };
+    let offset = metronome.position() - file_position;
+    let loops = metronome.loops();
+    let from = file_position - schedule.behind;
+    let to = file_position + schedule.lookahead;
+
+    let mut upcoming = Vec::new();
+    for (index, note) in schedule.notes.iter().enumerate() {
+        for (start, pass) in passes(note.start, song.info(), loops, metronome.is_looping()) {
+            if start < from || start > to {
+                continue;
+            }
+            upcoming.push(ScheduledNote {
+                id: NoteId { index, pass },
+                lane: note.lane,
+                kind: note.kind,
+                position: start + offset,
+                end: note.end - note.start + start + offset,
+            });
+        }
+    }
+    upcoming.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+    let schedule = &mut *schedule;
+    schedule
+        .judged
+        .retain(|id| upcoming.iter().any(|note| note.id == *id));
+    schedule.upcoming = upcoming;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioLength, Metre, Tempo};
+
+    // 120 BPM in 4/4, two seconds of intro and eight of body
+    fn info() -> AudioInfo {
+        AudioInfo {
+            tempo: Tempo(120.0),
+            metre: Metre::from((4, 4)),
+            intro: Some(AudioLength(2.0)),
+            body: AudioLength(8.0),
+            outro: None,
+        }
+    }
+
+    fn note(measure: u32, beat: u32, lane: UiButtonAction, kind: ChartNoteKind) -> ChartNote {
+        ChartNote {
+            measure,
+            beat,
+            subdivision: ChartNote::on_the_beat(),
+            lane,
+            kind,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn notes_are_timed_in_order() {
+        let mut offbeat = note(1, 1, UiButtonAction::Two, ChartNoteKind::Tap);
+        offbeat.subdivision = (1, 2);
+        let chart = ChartAsset(vec![
+            note(
+                1,
+                2,
+                UiButtonAction::Three,
+                ChartNoteKind::Hold { beats: 2.0 },
+            ),
+            offbeat,
+            note(1, 0, UiButtonAction::One, ChartNoteKind::Tap),
+        ]);
+        let timed = timed_notes(&chart, &TempoMap::new(&info(), &[]));
+        let starts = timed.iter().map(|note| note.start).collect::<Vec<_>>();
+        let lanes = timed.iter().map(|note| note.lane).collect::<Vec<_>>();
+        assert_eq!(
+            lanes,
+            [
+                UiButtonAction::One,
+                UiButtonAction::Two,
+                UiButtonAction::Three
+            ]
+        );
+        for (start, expected) in starts.into_iter().zip([2.0, 2.75, 3.0]) {
+            assert_close(start, expected);
+        }
+        assert_close(timed[0].end, timed[0].start);
+        assert_close(timed[2].end, 4.0);
+    }
+
+    #[test]
+    fn double_notes_take_both_lanes() {
+        let chart = ChartAsset(vec![note(
+            2,
+            0,
+            UiButtonAction::One,
+            ChartNoteKind::Double {
+                lane: UiButtonAction::Four,
+            },
+        )]);
+        let timed = timed_notes(&chart, &TempoMap::new(&info(), &[]));
+        assert_eq!(timed.len(), 2);
+        assert_eq!(timed[0].lane, UiButtonAction::One);
+        assert_eq!(timed[1].lane, UiButtonAction::Four);
+        assert_close(timed[0].start, 4.0);
+        assert_close(timed[1].start, 4.0);
+    }
+
+    #[test]
+    fn body_notes_are_seen_on_the_next_pass() {
+        assert_eq!(passes(3.0, &info(), 0, true), [(3.0, 0), (11.0, 1)]);
+    }
+
+    #[test]
+    fn body_notes_are_seen_on_the_previous_pass() {
+        assert_eq!(
+            passes(3.0, &info(), 2, true),
+            [(3.0, 2), (11.0, 3), (-5.0, 1)]
+        );
+    }
+
+    #[test]
+    fn finishing_songs_have_no_next_pass() {
+        assert_eq!(passes(3.0, &info(), 1, false), [(3.0, 1), (-5.0, 0)]);
+    }
+
+    #[test]
+    fn intro_notes_only_play_once() {
+        assert_eq!(passes(1.0, &info(), 0, true), [(1.0, 0)]);
+        assert_eq!(passes(1.0, &info(), 3, true), [(1.0, 3)]);
+    }
+}
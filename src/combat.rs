@@ -1,5 +1,17 @@
-use crate::CombatState;
+use crate::actions::UiButtonAction;
+use crate::audio::{
+    BeatEvent, CurrentSong, Metronome, MetronomeCommand, MetronomeEvent, MetronomeSet, NoteKind,
+};
+use crate::character::{DerivedStats, StatSheet};
+use crate::enemy::{Enemy, EnemyAsset};
+use crate::judgement::{Judgement, JudgementWindows};
+use crate::loading::{EnemyAssets, UiAssets};
+use crate::player::Player;
+use crate::ui::{Palette, UiButton, UiParentNode, UiParentNodePosition};
+use crate::{CombatState, PauseState};
 use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use rand::seq::SliceRandom;
 
 pub struct CombatPlugin;
 impl Plugin for CombatPlugin {
@@ -9,18 +21,689 @@ impl Plugin for CombatPlugin {
 
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(CombatState::In), startup)
-            .add_systems(OnExit(CombatState::Out), cleanup);
+            .add_systems(
+                Update,
+                (
+                    copy_player_stats,
+                    sync_combatants,
+                    player_commands,
+                    evr_advance_turns,
+                    record_presses,
+                    parry_enemy_hits,
+                    update_telegraphs,
+                    check_outcome,
+                    update_combat_text,
+                )
+                    .chain()
+                    .after(MetronomeSet)
+                    .run_if(in_state(CombatState::In).and(in_state(PauseState::Unpaused))),
+            )
+            .add_systems(
+                Update,
+                evr_encounter_ended.run_if(in_state(CombatState::In)),
+            )
+            .add_systems(OnExit(CombatState::In), cleanup)
+            .add_event::<EncounterStarted>()
+            .add_event::<EncounterEnded>();
     }
 }
 
+// DATA
+
+// Bars each side gets before the turn passes to the other
+const BARS_PER_TURN: u32 = 1;
+const ITEMS: u32 = 3;
+// Attack an enemy needs for its patterns to deal their listed damage
+const PATTERN_ATTACK: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CombatCommand {
+    Attack,
+    Defend,
+    Skill,
+    Item,
+}
+impl CombatCommand {
+    pub fn from_action(action: UiButtonAction) -> Self {
+        match action {
+            UiButtonAction::One => Self::Attack,
+            UiButtonAction::Two => Self::Defend,
+            UiButtonAction::Three => Self::Skill,
+            UiButtonAction::Four => Self::Item,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Turn {
+    #[default]
+    Player,
+    Enemy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncounterOutcome {
+    Victory,
+    Defeat,
+}
+
+// Encounters are entered by setting `CombatState::In`, this is sent once everything is spawned
+#[derive(Event, Clone, Copy, Debug)]
+pub struct EncounterStarted;
+
+// Sent when one side falls, leaving `CombatState::In` despawns everything tagged `CleanupCombat`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct EncounterEnded {
+    pub outcome: EncounterOutcome,
+}
+
+// State of the encounter in progress. `turn_end` is the measure the current turn ends on, unset
+// until the first downbeat so the first turn is a whole bar long.
+#[derive(Resource, Default)]
+pub struct Encounter {
+    pub turn: Turn,
+    pub turn_end: Option<u32>,
+    pub acted: bool,
+    // Fraction of the next enemy hit that is blocked
+    pub guard: f32,
+    pub items: u32,
+    // Attack patterns used so far, picks which one comes next
+    pub attacks: usize,
+    // Beats since the encounter began
+    pub beat: u64,
+    // Recent presses with the song position they happened at, for parrying
+    pub presses: Vec<(UiButtonAction, f32)>,
+    // Set once a side falls, the encounter only ends once
+    pub outcome: Option<EncounterOutcome>,
+}
+
+// One hit of an enemy attack, shown over the lane it must be parried on. `beat` is the beat of
+// the encounter it lands on and `impact` its song position, known once that beat arrives.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct EnemyHit {
+    pub beat: u64,
+    pub telegraph: u64,
+    pub lane: UiButtonAction,
+    pub damage: u32,
+    pub impact: Option<f32>,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Combatant {
+    pub name: String,
+    pub health: u32,
+    pub max_health: u32,
+    pub attack: u32,
+    pub defense: u32,
+}
+impl Combatant {
+    pub fn new(name: &str, max_health: u32, attack: u32, defense: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            health: max_health,
+            max_health,
+            attack,
+            defense,
+        }
+    }
+
+    pub fn is_defeated(&self) -> bool {
+        self.health == 0
+    }
+
+    // Returns the damage actually dealt
+    pub fn hurt(&mut self, damage: u32) -> u32 {
+        let damage = damage.min(self.health);
+        self.health -= damage;
+        damage
+    }
+
+    pub fn heal(&mut self, amount: u32) {
+        self.health = (self.health + amount).min(self.max_health);
+    }
+}
+
+#[derive(Component)]
+pub struct PlayerCombatant;
+
+#[derive(Component)]
+pub struct EnemyCombatant;
+
+#[derive(Component)]
+struct CombatText;
+
 #[derive(Component)]
 struct CleanupCombat;
 
-fn startup() {
+// How well a press was timed scales whatever it does
+fn power(judgement: Judgement) -> f32 {
+    match judgement {
+        Judgement::Perfect => 1.5,
+        Judgement::Great => 1.2,
+        Judgement::Good => 1.0,
+        Judgement::Miss => 0.0,
+    }
+}
+
+fn damage(attack: u32, defense: u32, multiplier: f32) -> u32 {
+    ((attack as f32 * multiplier).round() as u32).saturating_sub(defense)
+}
+
+// SYSTEMS
+
+#[allow(clippy::too_many_arguments)]
+fn startup(
+    mut commands: Commands,
+    ui: Res<UiAssets>,
+    enemy_assets: Res<EnemyAssets>,
+    enemies: Res<Assets<EnemyAsset>>,
+    current_song: Res<CurrentSong>,
+    query_player: Query<&DerivedStats, With<Player>>,
+    mut combat_state: ResMut<NextState<CombatState>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+    mut evw_encounter_started: EventWriter<EncounterStarted>,
+) {
     info!("[STARTUP] Combat");
+    let Some((handle, enemy)) = enemy_assets
+        .enemies
+        .choose(&mut rand::thread_rng())
+        .and_then(|handle| Some((handle, enemies.get(handle)?)))
+    else {
+        warn!("Failed to start encounter, no enemies loaded");
+        combat_state.set(CombatState::Out);
+        return;
+    };
+    commands.insert_resource(Encounter {
+        items: ITEMS,
+        ..default()
+    });
+    let player = query_player
+        .get_single()
+        .copied()
+        .unwrap_or_else(|_| DerivedStats::from(&StatSheet::default()));
+    commands.spawn((
+        Name::new("Combatant: Player"),
+        Combatant::new("Player", player.max_health, player.attack, player.defense),
+        player,
+        PlayerCombatant,
+        CleanupCombat,
+    ));
+    let sheet: StatSheet = enemy
+        .stats
+        .iter()
+        .map(|(kind, level)| (*kind, *level))
+        .collect();
+    let stats = DerivedStats::from(&sheet);
+    commands.spawn((
+        Name::new(format!("Combatant: {}", enemy.name)),
+        Combatant::new(
+            &enemy.name,
+            enemy.max_health(&stats),
+            stats.attack,
+            stats.defense,
+        ),
+        sheet,
+        stats,
+        EnemyCombatant,
+        Enemy(handle.clone()),
+        Sprite::from_atlas_image(
+            enemy.sprite.clone(),
+            TextureAtlas {
+                layout: enemy.layout.clone(),
+                index: 0,
+            },
+        ),
+        Transform::from_xyz(0., 40., 0.),
+        CleanupCombat,
+    ));
+    if let Some(song) = &current_song.0 {
+        evw_metronome.send(MetronomeEvent(MetronomeCommand::Play(song.clone())));
+    }
+
+    commands
+        .spawn((
+            Name::new("UI Parent Node: Combat"),
+            UiParentNode::new(UiParentNodePosition::Center, AlignItems::Start),
+            CleanupCombat,
+        ))
+        .with_child((
+            Name::new("Combat Text Node"),
+            Text::new(""),
+            TextFont {
+                font: ui.pixelify.clone(),
+                font_size: 8.0,
+                ..default()
+            },
+            TextColor(Palette::White.srgb()),
+            CombatText,
+        ));
+    evw_encounter_started.send(EncounterStarted);
+    info!("[SPAWNED] Combat");
+}
+
+// The player's stats live on the `Player`, the combatant keeps a copy for the encounter
+fn copy_player_stats(
+    query_player: Query<&DerivedStats, (With<Player>, Changed<DerivedStats>)>,
+    mut query_combatant: Query<&mut DerivedStats, (With<PlayerCombatant>, Without<Player>)>,
+) {
+    let Ok(stats) = query_player.get_single() else {
+        return;
+    };
+    for mut combatant_stats in &mut query_combatant {
+        combatant_stats.set_if_neq(*stats);
+    }
+}
+
+// Stats can change mid encounter, health is kept but never above the new maximum
+fn sync_combatants(
+    mut windows: ResMut<JudgementWindows>,
+    enemies: Res<Assets<EnemyAsset>>,
+    mut query_combatant: Query<
+        (
+            &mut Combatant,
+            &DerivedStats,
+            Option<&Enemy>,
+            Has<PlayerCombatant>,
+        ),
+        Changed<DerivedStats>,
+    >,
+) {
+    for (mut combatant, stats, enemy, is_player) in &mut query_combatant {
+        let max_health = enemy
+            .and_then(|enemy| enemies.get(&**enemy))
+            .map_or(stats.max_health, |enemy| enemy.max_health(stats));
+        combatant.max_health = max_health;
+        combatant.health = combatant.health.min(max_health);
+        combatant.attack = stats.attack;
+        combatant.defense = stats.defense;
+        if is_player {
+            *windows = JudgementWindows::default().scaled(stats.leniency);
+        }
+    }
+}
+
+// The first press during the player's turn picks their command for that turn. Presses are
+// judged against the beat rather than the chart, so every lane can be used on any beat.
+fn player_commands(
+    mut encounter: ResMut<Encounter>,
+    windows: Res<JudgementWindows>,
+    query_metronome: Query<&Metronome>,
+    query_button_action: Query<&ActionState<UiButtonAction>>,
+    mut query_player: Query<
+        (&mut Combatant, &DerivedStats),
+        (With<PlayerCombatant>, Without<EnemyCombatant>),
+    >,
+    mut query_enemy: Query<&mut Combatant, (With<EnemyCombatant>, Without<PlayerCombatant>)>,
+) {
+    if encounter.turn != Turn::Player || encounter.acted {
+        return;
+    }
+    let (Ok(metronome), Ok(action_state), Ok((mut player, stats)), Ok(mut enemy)) = (
+        query_metronome.get_single(),
+        query_button_action.get_single(),
+        query_player.get_single_mut(),
+        query_enemy.get_single_mut(),
+    ) else {
+        return;
+    };
+    let Some(action) = UiButtonAction::array()
+        .into_iter()
+        .find(|action| action_state.just_pressed(action))
+    else {
+        return;
+    };
+    let Some(beat) = metronome.nearest(NoteKind::Beat) else {
+        return;
+    };
+    let judgement = windows.judge((metronome.position() - beat) * 1000.0);
+    encounter.acted = true;
+    let command = CombatCommand::from_action(action);
+    if judgement == Judgement::Miss {
+        info!("[COMBAT] {command:?} missed the beat");
+        return;
+    }
+    let multiplier = power(judgement);
+    let defense = enemy.defense;
+    match command {
+        CombatCommand::Attack => {
+            let dealt = enemy.hurt(damage(player.attack, defense, multiplier));
+            info!("[COMBAT] {} hit {} for {dealt}", player.name, enemy.name);
+        }
+        CombatCommand::Defend => {
+            encounter.guard = (0.5 * multiplier).min(1.0);
+            info!(
+                "[COMBAT] {} guards {:.0}%",
+                player.name,
+                encounter.guard * 100.0
+            );
+        }
+        // Twice the damage, but only lands on a tightly timed press
+        CombatCommand::Skill => {
+            if judgement == Judgement::Good {
+                info!("[COMBAT] {command:?} fizzled");
+                return;
+            }
+            let dealt = enemy.hurt(damage(
+                player.attack * 2,
+                defense,
+                multiplier * stats.spell_power,
+            ));
+            info!(
+                "[COMBAT] {} blasted {} for {dealt}",
+                player.name, enemy.name
+            );
+        }
+        CombatCommand::Item => {
+            if encounter.items == 0 {
+                info!("[COMBAT] No items left");
+                return;
+            }
+            encounter.items -= 1;
+            let amount =
+                (player.max_health as f32 * 0.25 * multiplier * stats.healing).round() as u32;
+            player.heal(amount);
+            info!("[COMBAT] {} healed {amount}", player.name);
+        }
+    }
+}
+
+// Turns change on the downbeat. As the player's turn begins the enemy lines up its next
+// attack pattern for the turn after, so telegraphs can show before its first hit.
+#[allow(clippy::too_many_arguments)]
+fn evr_advance_turns(
+    mut commands: Commands,
+    mut encounter: ResMut<Encounter>,
+    ui: Res<UiAssets>,
+    enemies: Res<Assets<EnemyAsset>>,
+    query_metronome: Query<&Metronome>,
+    query_enemy: Query<&Enemy, With<EnemyCombatant>>,
+    query_ui_button: Query<(Entity, &UiButton)>,
+    mut query_hit: Query<&mut EnemyHit>,
+    mut evr_beat: EventReader<BeatEvent>,
+) {
+    let (Ok(metronome), Some(enemy)) = (
+        query_metronome.get_single(),
+        query_enemy
+            .get_single()
+            .ok()
+            .and_then(|handle| enemies.get(&**handle)),
+    ) else {
+        return;
+    };
+    for ev in evr_beat.read() {
+        if ev.kind != NoteKind::Beat {
+            continue;
+        }
+        encounter.beat += 1;
+        for mut hit in &mut query_hit {
+            if hit.beat == encounter.beat {
+                hit.impact = Some(ev.position);
+            }
+        }
+        if ev.beat != 0 {
+            continue;
+        }
+        match encounter.turn_end {
+            Some(turn_end) if ev.measure < turn_end => continue,
+            Some(_) => {
+                encounter.turn = match encounter.turn {
+                    Turn::Player => Turn::Enemy,
+                    Turn::Enemy => Turn::Player,
+                }
+            }
+            None => {}
+        }
+        encounter.turn_end = Some(ev.measure + BARS_PER_TURN);
+        encounter.acted = false;
+        if encounter.turn == Turn::Enemy {
+            continue;
+        }
+        encounter.guard = 0.0;
+        if enemy.attacks.is_empty() {
+            continue;
+        }
+        let pattern = &enemy.attacks[encounter.attacks % enemy.attacks.len()];
+        encounter.attacks += 1;
+        // Assumes the metre holds until the enemy's turn
+        let beats = metronome.metre().map_or(4, |metre| metre.beats() as u64);
+        let enemy_turn = encounter.beat + beats * BARS_PER_TURN as u64;
+        info!("[COMBAT] {} readies {}", enemy.name, pattern.name);
+        for attack_hit in &pattern.hits {
+            let Some((button, _)) = query_ui_button
+                .iter()
+                .find(|&(_, ui_button)| ***ui_button == attack_hit.lane.index())
+            else {
+                continue;
+            };
+            let hit = commands
+                .spawn((
+                    Name::new(format!("Enemy Hit: {}", pattern.name)),
+                    EnemyHit {
+                        beat: enemy_turn + attack_hit.beat as u64,
+                        telegraph: pattern.telegraph as u64,
+                        lane: attack_hit.lane,
+                        damage: pattern.damage,
+                        impact: None,
+                    },
+                    Node {
+                        position_type: PositionType::Absolute,
+                        top: Val::Px(-10.),
+                        ..default()
+                    },
+                    Text::new(""),
+                    TextFont {
+                        font: ui.pixelify.clone(),
+                        font_size: 8.0,
+                        ..default()
+                    },
+                    TextColor(Palette::White.srgb()),
+                    Visibility::Hidden,
+                    CleanupCombat,
+                ))
+                .id();
+            commands.entity(button).add_child(hit);
+        }
+    }
+}
+
+// Presses are kept for the length of the widest window so an early press still counts once
+// the impact beat arrives
+fn record_presses(
+    mut encounter: ResMut<Encounter>,
+    windows: Res<JudgementWindows>,
+    query_metronome: Query<&Metronome>,
+    query_button_action: Query<&ActionState<UiButtonAction>>,
+) {
+    let Ok(metronome) = query_metronome.get_single() else {
+        return;
+    };
+    let position = metronome.position();
+    let window = windows.good / 1000.0;
+    if let Ok(action_state) = query_button_action.get_single() {
+        for action in UiButtonAction::array() {
+            if action_state.just_pressed(&action) {
+                encounter.presses.push((action, position));
+            }
+        }
+    }
+    encounter
+        .presses
+        .retain(|(_, pressed)| position - pressed <= window);
+}
+
+// A hit is parried by pressing its lane on the impact beat, the better the timing the less
+// of it gets through
+fn parry_enemy_hits(
+    mut commands: Commands,
+    mut encounter: ResMut<Encounter>,
+    windows: Res<JudgementWindows>,
+    query_metronome: Query<&Metronome>,
+    mut query_player: Query<&mut Combatant, (With<PlayerCombatant>, Without<EnemyCombatant>)>,
+    query_enemy: Query<&Combatant, (With<EnemyCombatant>, Without<PlayerCombatant>)>,
+    query_hit: Query<(Entity, &EnemyHit)>,
+) {
+    let (Ok(metronome), Ok(mut player), Ok(enemy)) = (
+        query_metronome.get_single(),
+        query_player.get_single_mut(),
+        query_enemy.get_single(),
+    ) else {
+        return;
+    };
+    let strength = enemy.attack as f32 / PATTERN_ATTACK as f32;
+    let position = metronome.position();
+    let window = windows.good / 1000.0;
+    for (entity, hit) in &query_hit {
+        let Some(impact) = hit.impact else {
+            continue;
+        };
+        let press = encounter
+            .presses
+            .iter()
+            .position(|(lane, pressed)| *lane == hit.lane && (pressed - impact).abs() <= window);
+        let judgement = match press {
+            Some(index) => {
+                let (_, pressed) = encounter.presses.remove(index);
+                windows.judge((pressed - impact) * 1000.0)
+            }
+            // Still time for a late press
+            None if position - impact <= window => continue,
+            None => Judgement::Miss,
+        };
+        let taken = match judgement {
+            Judgement::Perfect => 0.0,
+            Judgement::Great => 0.25,
+            Judgement::Good => 0.5,
+            Judgement::Miss => 1.0,
+        } * (1.0 - encounter.guard)
+            * strength;
+        let defense = player.defense;
+        let dealt = player.hurt(damage(hit.damage, defense, taken));
+        info!("[COMBAT] {:?} {judgement:?}, took {dealt}", hit.lane);
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
-fn cleanup(mut commands: Commands, query_cleanup: Query<Entity, With<CleanupCombat>>) {
+// Counts down the beats left on each hit once it is within its telegraph, a beat early when the
+// visual latency means the next beat should already be on screen
+fn update_telegraphs(
+    encounter: Res<Encounter>,
+    query_metronome: Query<&Metronome>,
+    mut query_hit: Query<(&EnemyHit, &mut Visibility, &mut Text)>,
+) {
+    let lead = query_metronome
+        .get_single()
+        .is_ok_and(|metronome| metronome.visual_lead(NoteKind::Beat));
+    let beat = encounter.beat + lead as u64;
+    for (hit, mut visibility, mut text) in &mut query_hit {
+        let remaining = hit.beat.saturating_sub(beat);
+        if remaining > hit.telegraph {
+            continue;
+        }
+        visibility.set_if_neq(Visibility::Inherited);
+        let countdown = if remaining == 0 {
+            "!".to_string()
+        } else {
+            remaining.to_string()
+        };
+        if text.0 != countdown {
+            text.0 = countdown;
+        }
+    }
+}
+
+fn check_outcome(
+    mut encounter: ResMut<Encounter>,
+    enemies: Res<Assets<EnemyAsset>>,
+    query_player: Query<(&Combatant, &DerivedStats), With<PlayerCombatant>>,
+    query_enemy: Query<(&Combatant, &Enemy), With<EnemyCombatant>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+    mut evw_encounter_ended: EventWriter<EncounterEnded>,
+) {
+    if encounter.outcome.is_some() {
+        return;
+    }
+    let (Ok((player, stats)), Ok((enemy, handle))) =
+        (query_player.get_single(), query_enemy.get_single())
+    else {
+        return;
+    };
+    let outcome = if player.is_defeated() {
+        EncounterOutcome::Defeat
+    } else if enemy.is_defeated() {
+        EncounterOutcome::Victory
+    } else {
+        return;
+    };
+    encounter.outcome = Some(outcome);
+    info!("[COMBAT] Outcome: {outcome:?}");
+    if outcome == EncounterOutcome::Victory {
+        // TODO: Add drops to an inventory
+        for drop in enemies
+            .get(&**handle)
+            .into_iter()
+            .flat_map(|asset| &asset.drops)
+        {
+            if rand::random::<f32>() < drop.chance * stats.luck {
+                info!("[DROPPED] {}", drop.item);
+            }
+        }
+    }
+    evw_metronome.send(MetronomeEvent(MetronomeCommand::Finish));
+    evw_encounter_ended.send(EncounterEnded { outcome });
+}
+
+fn evr_encounter_ended(
+    mut combat_state: ResMut<NextState<CombatState>>,
+    mut evr_encounter_ended: EventReader<EncounterEnded>,
+) {
+    if evr_encounter_ended.read().last().is_some() {
+        combat_state.set(CombatState::Out);
+    }
+}
+
+fn update_combat_text(
+    encounter: Res<Encounter>,
+    query_player: Query<&Combatant, With<PlayerCombatant>>,
+    query_enemy: Query<&Combatant, With<EnemyCombatant>>,
+    mut query_text: Query<&mut Text, With<CombatText>>,
+) {
+    let (Ok(player), Ok(enemy)) = (query_player.get_single(), query_enemy.get_single()) else {
+        return;
+    };
+    let turn = match encounter.turn {
+        Turn::Player => "Your turn",
+        Turn::Enemy => "Enemy turn",
+    };
+    let text = format!(
+        "{} {}/{}  {} {}/{}\n{turn}  Items {}",
+        player.name,
+        player.health,
+        player.max_health,
+        enemy.name,
+        enemy.health,
+        enemy.max_health,
+        encounter.items,
+    );
+    for mut combat_text in &mut query_text {
+        if combat_text.0 != text {
+            combat_text.0 = text.clone();
+        }
+    }
+}
+
+fn cleanup(
+    mut commands: Commands,
+    encounter: Option<Res<Encounter>>,
+    mut windows: ResMut<JudgementWindows>,
+    query_cleanup: Query<Entity, With<CleanupCombat>>,
+    mut evw_metronome: EventWriter<MetronomeEvent>,
+) {
+    // A finished encounter lets the song play out its outro, the metronome stops after it
+    if encounter.is_none_or(|encounter| encounter.outcome.is_none()) {
+        evw_metronome.send(MetronomeEvent(MetronomeCommand::Stop));
+    }
+    *windows = JudgementWindows::default();
+    commands.remove_resource::<Encounter>();
     for entity in query_cleanup.iter() {
         commands.entity(entity).despawn_recursive();
         info!("[CLEANUP] Combat");
@@ -0,0 +1,218 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{ron, AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+use crate::actions::UiButtonAction;
+use crate::audio::{BeatEvent, Metronome, MetronomeSet, NoteKind};
+use crate::character::{DerivedStats, StatKind};
+use crate::loader::RonLoaderError;
+
+pub struct EnemyPlugin;
+impl Plugin for EnemyPlugin {
+    fn name(&self) -> &str {
+        "Enemy Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, evr_animate_enemies.after(MetronomeSet))
+            .init_asset::<EnemyAsset>()
+            .init_asset_loader::<EnemyLoader>();
+    }
+}
+
+// DATA
+
+#[derive(Asset, TypePath)]
+pub struct EnemyAsset {
+    pub name: String,
+    pub sprite: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+    pub frames: usize,
+    // Replaces the maximum health derived from `stats`
+    pub health: Option<u32>,
+    pub stats: HashMap<StatKind, u8>,
+    pub attacks: Vec<AttackPattern>,
+    pub drops: Vec<EnemyDrop>,
+}
+
+// On disk layout of a `.enemy` file, e.g.
+// (
+//     name: "Slime",
+//     sprite: (path: "textures/enemies/slime.png", tile_size: (32, 32), frames: 2),
+//     health: Some(24),
+//     stats: {Constitution: 3, Agility: 1},
+//     attacks: [
+//         (name: "Bounce", damage: 3, hits: [(beat: 0, lane: One), (beat: 2, lane: One)]),
+//         (name: "Splash", damage: 2, hits: [
+//             (beat: 0, lane: Two), (beat: 1, lane: Three), (beat: 2, lane: Two),
+//         ]),
+//     ],
+//     drops: [(item: "Slime Gel", chance: 0.5)],
+// )
+// Attack beats count from the start of the enemy's turn and `telegraph` defaults to 2 beats,
+// `sprite.path` is relative to the assets folder and its frames are laid out in a single row.
+// Health, attack and defense are derived from `stats` like the player's. `health` is optional
+// and replaces the derived maximum health, stats left out stay at the base level.
+#[derive(Deserialize)]
+struct EnemyFile {
+    name: String,
+    sprite: SpriteFile,
+    health: Option<u32>,
+    #[serde(default)]
+    stats: HashMap<StatKind, u8>,
+    #[serde(default)]
+    attacks: Vec<AttackPattern>,
+    #[serde(default)]
+    drops: Vec<EnemyDrop>,
+}
+
+#[derive(Deserialize)]
+struct SpriteFile {
+    path: String,
+    tile_size: (u32, u32),
+    frames: u32,
+}
+
+impl EnemyAsset {
+    pub fn max_health(&self, stats: &DerivedStats) -> u32 {
+        self.health.unwrap_or(stats.max_health)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AttackPattern {
+    pub name: String,
+    // Damage of every hit that isn't parried, scaled by the enemy's attack
+    pub damage: u32,
+    pub hits: Vec<AttackHit>,
+    // Beats of warning shown before each hit lands
+    #[serde(default = "AttackPattern::default_telegraph")]
+    pub telegraph: u32,
+}
+impl AttackPattern {
+    fn default_telegraph() -> u32 {
+        2
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct AttackHit {
+    pub beat: u32,
+    pub lane: UiButtonAction,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnemyDrop {
+    pub item: String,
+    // Between 0 and 1
+    pub chance: f32,
+}
+
+#[derive(Component, Deref)]
+pub struct Enemy(pub Handle<EnemyAsset>);
+
+#[derive(Default)]
+pub struct EnemyLoader;
+impl AssetLoader for EnemyLoader {
+    type Asset = EnemyAsset;
+    type Settings = ();
+    type Error = RonLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file: EnemyFile = ron::de::from_bytes(&bytes)?;
+        file.validate()?;
+
+        let (width, height) = file.sprite.tile_size;
+        let layout = TextureAtlasLayout::from_grid(
+            UVec2::new(width, height),
+            file.sprite.frames,
+            1,
+            None,
+            None,
+        );
+        Ok(EnemyAsset {
+            sprite: load_context.load(file.sprite.path),
+            layout: load_context.add_labeled_asset("layout".to_string(), layout),
+            frames: file.sprite.frames as usize,
+            name: file.name,
+            health: file.health,
+            stats: file.stats,
+            attacks: file.attacks,
+            drops: file.drops,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["enemy"]
+    }
+}
+
+impl EnemyFile {
+    fn validate(&self) -> Result<(), RonLoaderError> {
+        if self.health == Some(0) {
+            return Err(RonLoaderError::Invalid("health must be positive"));
+        }
+        let (width, height) = self.sprite.tile_size;
+        if width == 0 || height == 0 || self.sprite.frames == 0 {
+            return Err(RonLoaderError::Invalid(
+                "sprite needs a non-zero tile size and at least one frame",
+            ));
+        }
+        if self.attacks.iter().any(|attack| attack.hits.is_empty()) {
+            return Err(RonLoaderError::Invalid("attacks need at least one hit"));
+        }
+        if self
+            .drops
+            .iter()
+            .any(|drop| !(0.0..=1.0).contains(&drop.chance))
+        {
+            return Err(RonLoaderError::Invalid(
+                "drop chances must be between 0 and 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// SYSTEMS
+
+// Enemies bob through their frames in time with the music. With visual latency a frame is shown
+// as soon as the visual position reaches the beat, and skipped when its `BeatEvent` arrives.
+fn evr_animate_enemies(
+    mut ahead: Local<bool>,
+    enemies: Res<Assets<EnemyAsset>>,
+    query_metronome: Query<&Metronome>,
+    mut query_enemy: Query<(&Enemy, &mut Sprite)>,
+    mut evr_beat: EventReader<BeatEvent>,
+) {
+    let lead = query_metronome
+        .get_single()
+        .is_ok_and(|metronome| metronome.visual_lead(NoteKind::Beat));
+    let beats = evr_beat
+        .read()
+        .filter(|ev| ev.kind == NoteKind::Beat)
+        .count()
+        + lead as usize;
+    let beats = beats.saturating_sub(*ahead as usize);
+    *ahead = lead;
+    if beats == 0 {
+        return;
+    }
+    for (enemy, mut sprite) in &mut query_enemy {
+        let Some(asset) = enemies.get(&**enemy) else {
+            continue;
+        };
+        if let Some(atlas) = &mut sprite.texture_atlas {
+            atlas.index = (atlas.index + beats) % asset.frames;
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::actions::UiButtonAction;
+use crate::audio::{Metronome, MetronomeSet, MetronomeState, NoteKind};
+use crate::chart::{ChartNoteKind, ChartSchedule};
+
+pub struct JudgementPlugin;
+impl Plugin for JudgementPlugin {
+    fn name(&self) -> &str {
+        "Judgement Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (judge_input, miss_passed_notes)
+                .chain()
+                .after(MetronomeSet)
+                .run_if(in_state(MetronomeState::Playing)),
+        )
+        .init_resource::<JudgementWindows>()
+        .add_event::<JudgementEvent>();
+    }
+}
+
+// DATA
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Judgement {
+    Perfect,
+    Great,
+    Good,
+    Miss,
+}
+
+// `offset` is in milliseconds, negative when the press came early
+#[derive(Event, Clone, Copy, Debug)]
+pub struct JudgementEvent {
+    pub action: UiButtonAction,
+    pub judgement: Judgement,
+    pub offset: f32,
+}
+
+// Half widths of each timing window in milliseconds. Presses are judged against the notes of
+// the active chart, or against the notes of `grid` when the song has no chart.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct JudgementWindows {
+    pub grid: NoteKind,
+    pub perfect: f32,
+    pub great: f32,
+    pub good: f32,
+}
+impl Default for JudgementWindows {
+    fn default() -> Self {
+        Self {
+            grid: NoteKind::Beat,
+            perfect: 35.0,
+            great: 70.0,
+            good: 110.0,
+        }
+    }
+}
+impl JudgementWindows {
+    // Every window widened by `leniency`
+    pub fn scaled(&self, leniency: f32) -> Self {
+        Self {
+            perfect: self.perfect * leniency,
+            great: self.great * leniency,
+            good: self.good * leniency,
+            ..*self
+        }
+    }
+
+    pub fn judge(&self, offset: f32) -> Judgement {
+        match offset.abs() {
+            o if o <= self.perfect => Judgement::Perfect,
+            o if o <= self.great => Judgement::Great,
+            o if o <= self.good => Judgement::Good,
+            _ => Judgement::Miss,
+        }
+    }
+}
+
+// SYSTEMS
+
+fn judge_input(
+    windows: Res<JudgementWindows>,
+    mut schedule: ResMut<ChartSchedule>,
+    query_metronome: Query<&Metronome>,
+    query_button_action: Query<&ActionState<UiButtonAction>>,
+    mut evw_judgement: EventWriter<JudgementEvent>,
+) {
+    let (Ok(metronome), Ok(action_state)) = (
+        query_metronome.get_single(),
+        query_button_action.get_single(),
+    ) else {
+        return;
+    };
+    let position = metronome.position();
+    for action in UiButtonAction::array() {
+        // Holds are judged again on release against their end, letting go early is a miss
+        if action_state.just_released(&action) {
+            if let Some(note) = schedule.holding.remove(&action) {
+                let offset = (position - note.end) * 1000.0;
+                let judgement = windows.judge(offset);
+                info!("[JUDGED] {action:?}: {judgement:?} ({offset:+.1}ms released)");
+                evw_judgement.send(JudgementEvent {
+                    action,
+                    judgement,
+                    offset,
+                });
+            }
+        }
+        if !action_state.just_pressed(&action) {
+            continue;
+        }
+        let (expected, id) = if schedule.is_active() {
+            let Some(note) = schedule.nearest(action, position).copied() else {
+                continue;
+            };
+            if matches!(note.kind, ChartNoteKind::Hold { .. }) {
+                schedule.holding.insert(action, note);
+            }
+            (note.position, Some(note.id))
+        } else {
+            let Some(expected) = metronome.nearest(windows.grid) else {
+                continue;
+            };
+            (expected, None)
+        };
+        let offset = (position - expected) * 1000.0;
+        let judgement = windows.judge(offset);
+        // A press too far from its note leaves the note open to be hit properly
+        if judgement == Judgement::Miss {
+            schedule.holding.remove(&action);
+        } else if let Some(id) = id {
+            schedule.judged.insert(id);
+        }
+        info!("[JUDGED] {action:?}: {judgement:?} ({offset:+.1}ms)");
+        evw_judgement.send(JudgementEvent {
+            action,
+            judgement,
+            offset,
+        });
+    }
+}
+
+fn miss_passed_notes(
+    windows: Res<JudgementWindows>,
+    mut schedule: ResMut<ChartSchedule>,
+    query_metronome: Query<&Metronome>,
+    mut evw_judgement: EventWriter<JudgementEvent>,
+) {
+    let Ok(metronome) = query_metronome.get_single() else {
+        return;
+    };
+    let position = metronome.position();
+    let schedule = &mut *schedule;
+    // Holds kept down past the end of their window count as released too late
+    schedule.holding.retain(|action, note| {
+        let offset = (position - note.end) * 1000.0;
+        if offset <= windows.good {
+            return true;
+        }
+        info!("[JUDGED] {action:?}: {:?} (held)", Judgement::Miss);
+        evw_judgement.send(JudgementEvent {
+            action: *action,
+            judgement: Judgement::Miss,
+            offset,
+        });
+        false
+    });
+    for note in &schedule.upcoming {
+        let offset = (position - note.position) * 1000.0;
+        if offset <= windows.good || !schedule.judged.insert(note.id) {
+            continue;
+        }
+        info!("[JUDGED] {:?}: {:?} (passed)", note.lane, Judgement::Miss);
+        evw_judgement.send(JudgementEvent {
+            action: note.lane,
+            judgement: Judgement::Miss,
+            offset,
+        });
+    }
+}
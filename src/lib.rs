@@ -2,18 +2,31 @@
 
 mod actions;
 mod audio;
+mod calibration;
+mod canvas;
 mod character;
+mod chart;
 mod combat;
+mod enemy;
+mod judgement;
+mod loader;
 mod loading;
 mod menu;
 mod player;
 mod settings;
+mod song;
+mod storage;
+mod tempo;
 mod ui;
 
 use std::io::Cursor;
 
 use crate::actions::ActionsPlugin;
 use crate::audio::InternalAudioPlugin;
+use crate::calibration::CalibrationPlugin;
+use crate::canvas::CanvasPlugin;
+use crate::character::CharacterPlugin;
+use crate::enemy::EnemyPlugin;
 use crate::loading::LoadingPlugin;
 use crate::menu::MenuPlugin;
 use crate::player::PlayerPlugin;
@@ -27,8 +40,11 @@ use bevy::window::PrimaryWindow;
 use bevy::winit::WinitWindows;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_kira_audio::AudioPlugin;
+use chart::ChartPlugin;
 use combat::CombatPlugin;
+use judgement::JudgementPlugin;
 use settings::SettingsPlugin;
+use song::SongPlugin;
 use ui::{Palette, UiPlugin};
 use winit::window::Icon;
 
@@ -52,7 +68,7 @@ impl Plugin for GamePlugin {
                         prevent_default_event_handling: false,
                         window_theme: Some(bevy::window::WindowTheme::Dark),
                         resizable: false,
-                        resolution: WindowResolution::new(320., 288.),
+                        resolution: WindowResolution::new(640., 360.),
                         ..default()
                     }),
                     ..default()
@@ -74,12 +90,20 @@ impl Plugin for GamePlugin {
             UiPlugin,
             CombatPlugin,
             SettingsPlugin,
+            SongPlugin,
+            JudgementPlugin,
+            ChartPlugin,
+            CalibrationPlugin,
+            CanvasPlugin,
+            EnemyPlugin,
+            CharacterPlugin,
         ))
         .add_systems(Startup, startup)
         .init_state::<GameState>()
         .insert_resource(ClearColor(Palette::Darker.srgb()))
         .add_sub_state::<PauseState>()
-        .add_sub_state::<CombatState>();
+        .add_sub_state::<CombatState>()
+        .add_sub_state::<SettingsState>();
 
         /* #[cfg(debug_assertions)]
         {
@@ -108,11 +132,19 @@ pub enum PauseState {
 #[derive(SubStates, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 #[source(GameState = GameState::Playing)]
 pub enum CombatState {
-    Out,
     #[default]
+    Out,
     In,
 }
 
+#[derive(SubStates, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+#[source(GameState = GameState::Settings)]
+pub enum SettingsState {
+    #[default]
+    Main,
+    Calibration,
+}
+
 fn startup(windows: NonSend<WinitWindows>, primary_window: Query<Entity, With<PrimaryWindow>>) {
     let primary_entity = primary_window.single();
     let Some(primary) = windows.get_window(primary_entity) else {
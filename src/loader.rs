@@ -0,0 +1,30 @@
+use bevy::asset::ron;
+
+// Error of every loader reading a RON asset file. Bevy reports it with the asset path and the
+// loader, so it doesn't need to say which kind of file failed.
+#[derive(Debug)]
+pub enum RonLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Invalid(&'static str),
+}
+impl std::fmt::Display for RonLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RonLoaderError::Io(error) => write!(f, "Could not read file: {error}"),
+            RonLoaderError::Ron(error) => write!(f, "Could not parse file: {error}"),
+            RonLoaderError::Invalid(reason) => write!(f, "Invalid file: {reason}"),
+        }
+    }
+}
+impl std::error::Error for RonLoaderError {}
+impl From<std::io::Error> for RonLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        RonLoaderError::Io(error)
+    }
+}
+impl From<ron::error::SpannedError> for RonLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        RonLoaderError::Ron(error)
+    }
+}
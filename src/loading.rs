@@ -1,6 +1,9 @@
+use crate::enemy::EnemyAsset;
+use crate::song::SongAsset;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
+use bevy_kira_audio::AudioSource;
 
 pub struct LoadingPlugin;
 impl Plugin for LoadingPlugin {
@@ -12,7 +15,10 @@ impl Plugin for LoadingPlugin {
         app.add_loading_state(
             LoadingState::new(GameState::Loading)
                 .continue_to_state(GameState::Menu)
-                .load_collection::<UiAssets>(),
+                .with_dynamic_assets_file::<StandardDynamicAssetCollection>("enemies.assets.ron")
+                .load_collection::<UiAssets>()
+                .load_collection::<AudioAssets>()
+                .load_collection::<EnemyAssets>(),
         );
     }
 }
@@ -34,3 +40,22 @@ pub struct UiAssets {
     #[asset(path = "fonts/PixelifySans-Regular.ttf")]
     pub pixelify: Handle<Font>,
 }
+
+#[derive(AssetCollection, Resource)]
+pub struct AudioAssets {
+    #[asset(path = "audio/songs/demo.song")]
+    pub demo: Handle<SongAsset>,
+    #[asset(path = "audio/songs/calibration.song")]
+    pub calibration: Handle<SongAsset>,
+    #[asset(path = "audio/click.wav")]
+    pub click: Handle<AudioSource>,
+    #[asset(path = "audio/click_accent.wav")]
+    pub click_accent: Handle<AudioSource>,
+}
+
+// Listed in `enemies.assets.ron` so new enemies only need their files added there
+#[derive(AssetCollection, Resource)]
+pub struct EnemyAssets {
+    #[asset(key = "enemies", collection(typed))]
+    pub enemies: Vec<Handle<EnemyAsset>>,
+}
@@ -1,18 +1,29 @@
+use crate::actions::UiButtonAction;
+use crate::combat::EncounterEnded;
 use crate::loading::UiAssets;
-use crate::settings::Settings;
 use crate::ui::{
-    Palette, UiBackgroundColor, UiBorderColor, UiButton, UiButtonNode, UiButtonRow, UiButtonState,
-    UiParentNode, UiParentNodePosition, UiTextColor,
+    Focusable, Palette, UiBackgroundColor, UiBorderColor, UiButton, UiButtonAnimation,
+    UiButtonNode, UiButtonPressed, UiButtonRow, UiButtonState, UiParentNode, UiParentNodePosition,
+    UiTextColor,
 };
 use crate::{CombatState, GameState};
 use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Menu), startup)
-            .add_systems(Update, click_ui_buttons.run_if(in_state(GameState::Menu)))
+        app.add_systems(OnExit(GameState::Loading), spawn_hud)
+            .add_systems(OnEnter(GameState::Menu), startup)
+            .add_systems(
+                Update,
+                (click_ui_buttons, press_ui_buttons).run_if(in_state(GameState::Menu)),
+            )
+            .add_systems(
+                Update,
+                evr_encounter_ended.run_if(in_state(GameState::Playing)),
+            )
             .add_systems(OnExit(GameState::Menu), cleanup);
     }
 }
@@ -33,21 +44,11 @@ enum MainMenuButton {
 #[derive(Component)]
 struct CleanupMainMenu;
 
-fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
-    info!("[STARUP] Main Menu");
-    // Spawn Main Camera
-    commands.spawn((Camera2d, Msaa::Off));
-
-    // Style data
-    let style = (
-        BackgroundColor(UiBackgroundColor::default().normal.srgb()),
-        BorderColor(UiBorderColor::default().normal.srgb()),
-        BorderRadius::ZERO,
-    );
-    let scale = settings.resolution.scale.scale();
+// The HUD stays up for the whole game, only the menu buttons come and go
+fn spawn_hud(mut commands: Commands, ui: Res<UiAssets>) {
     let text_font = TextFont {
         font: ui.pixelify.clone(),
-        font_size: 20.0, // TODO: Programatically size
+        font_size: 8.0,
         ..default()
     };
     let text_color = TextColor(Palette::White.srgb());
@@ -70,8 +71,8 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                 },
             ),
             Node {
-                width: Val::Px(40. * scale),
-                height: Val::Px(40. * scale),
+                width: Val::Px(40.),
+                height: Val::Px(40.),
                 ..default()
             },
         ))
@@ -80,8 +81,8 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
         .spawn((
             Name::new("Pendulum Text Node"),
             Node {
-                width: Val::Px(80. * scale),
-                height: Val::Px(80. * scale),
+                width: Val::Px(80.),
+                height: Val::Px(80.),
                 flex_direction: FlexDirection::Column,
                 ..default()
             },
@@ -92,34 +93,28 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
             Name::new("BPM Text Node"),
             Text::new("bpm: "),
             text_font.clone(),
-            text_color.clone(),
+            text_color,
             Node {
-                width: Val::Px(40. * scale),
-                height: Val::Px(20. * scale),
+                width: Val::Px(40.),
+                height: Val::Px(20.),
                 ..default()
             },
         ))
-        .with_child((
-            TextSpan::new("NaN"),
-            (text_font.clone(), text_color.clone()),
-        ))
+        .with_child((TextSpan::new("NaN"), (text_font.clone(), text_color)))
         .id();
     let dur_text = commands
         .spawn((
             Name::new("Duration Text Node"),
             Text::new("dur: "),
             text_font.clone(),
-            text_color.clone(),
+            text_color,
             Node {
-                width: Val::Px(40. * scale),
-                height: Val::Px(20. * scale),
+                width: Val::Px(40.),
+                height: Val::Px(20.),
                 ..default()
             },
         ))
-        .with_child((
-            TextSpan::new("NaN"),
-            (text_font.clone(), text_color.clone()),
-        ))
+        .with_child((TextSpan::new("NaN"), (text_font.clone(), text_color)))
         .id();
 
     commands
@@ -161,11 +156,13 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                     },
                 ),
                 Node {
-                    width: Val::Px(40. * settings.resolution.scale.scale()),
-                    height: Val::Px(40. * settings.resolution.scale.scale()),
+                    width: Val::Px(40.),
+                    height: Val::Px(40.),
                     ..default()
                 },
                 UiButton(UiButtonRow(i)),
+                UiButtonPressed::default(),
+                UiButtonAnimation::default(),
                 state,
             ))
             .id();
@@ -173,6 +170,17 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
         commands.entity(center_entity).add_child(child);
         info!("[SPAWNED] UI Button: {i}");
     }
+}
+
+fn startup(mut commands: Commands, ui: Res<UiAssets>) {
+    info!("[STARUP] Main Menu");
+
+    // Style data
+    let style = (
+        BackgroundColor(UiBackgroundColor::default().normal.srgb()),
+        BorderColor(UiBorderColor::default().normal.srgb()),
+        BorderRadius::ZERO,
+    );
 
     commands
         .spawn((
@@ -181,7 +189,7 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                 flex_direction: FlexDirection::Row,
                 align_items: AlignItems::Center,
                 justify_content: JustifyContent::End,
-                bottom: Val::Px(5.),
+                bottom: Val::Px(2.),
                 width: Val::Percent(100.),
                 position_type: PositionType::Absolute,
                 ..default()
@@ -190,13 +198,50 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
             CleanupMainMenu,
         ))
         .with_children(|children| {
+            children
+                .spawn((
+                    Name::new("Play Child Node"),
+                    Button,
+                    MainMenuButton::Play,
+                    Focusable,
+                    UiButtonNode::small(),
+                    style,
+                ))
+                .with_child((
+                    Name::new("Play Text Node"),
+                    Text::new("Play"),
+                    TextFont {
+                        font_size: 6.0,
+                        ..default()
+                    },
+                    TextColor(UiTextColor::default().normal.srgb()),
+                ));
+            children
+                .spawn((
+                    Name::new("Settings Child Node"),
+                    Button,
+                    MainMenuButton::Settings,
+                    Focusable,
+                    UiButtonNode::small(),
+                    style,
+                ))
+                .with_child((
+                    Name::new("Settings Text Node"),
+                    Text::new("Settings"),
+                    TextFont {
+                        font_size: 6.0,
+                        ..default()
+                    },
+                    TextColor(UiTextColor::default().normal.srgb()),
+                ));
             children
                 .spawn((
                     Name::new("Bevy Logo Child Node"),
                     Button,
                     MainMenuButton::Bevy,
+                    Focusable,
                     UiButtonNode::small(),
-                    style.clone(),
+                    style,
                     OpenLink("https://bevyengine.org"),
                 ))
                 .with_children(|parent| {
@@ -204,7 +249,7 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                         Name::new("Bevy Logo Grandchild Node"),
                         Text::new("Made with Bevy"),
                         TextFont {
-                            font_size: 15.0,
+                            font_size: 6.0,
                             ..default()
                         },
                         TextColor(UiTextColor::default().normal.srgb()),
@@ -215,7 +260,7 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                             ..default()
                         },
                         Node {
-                            width: Val::Px(32.),
+                            width: Val::Px(11.),
                             ..default()
                         },
                     ));
@@ -225,8 +270,9 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                     Name::new("Github Parent Node"),
                     Button,
                     MainMenuButton::Github,
+                    Focusable,
                     UiButtonNode::small(),
-                    style.clone(),
+                    style,
                     OpenLink("https://github.com/AwfullyMatt/chrysopoeia"),
                 ))
                 .with_children(|parent| {
@@ -234,7 +280,7 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                         Name::new("Github Text Node"),
                         Text::new("Github"),
                         TextFont {
-                            font_size: 15.0,
+                            font_size: 6.0,
                             ..default()
                         },
                         TextColor(UiTextColor::default().normal.srgb()),
@@ -243,7 +289,7 @@ fn startup(mut commands: Commands, ui: Res<UiAssets>, settings: Res<Settings>) {
                         Name::new("Github Image Node"),
                         ImageNode::new(ui.github.clone()),
                         Node {
-                            width: Val::Px(32.),
+                            width: Val::Px(11.),
                             ..default()
                         },
                     ));
@@ -269,7 +315,7 @@ fn click_ui_buttons(
                     game_state.set(GameState::Playing);
                     combat_state.set(CombatState::In);
                 }
-                MainMenuButton::Settings => {}
+                MainMenuButton::Settings => game_state.set(GameState::Settings),
                 MainMenuButton::Exit => {}
                 MainMenuButton::Github => {
                     if let Some(link) = open_link {
@@ -292,6 +338,29 @@ fn click_ui_buttons(
     }
 }
 
+// The third button shows the settings icon on the menu
+fn press_ui_buttons(
+    mut game_state: ResMut<NextState<GameState>>,
+    query_button_action: Query<&ActionState<UiButtonAction>>,
+) {
+    if let Ok(action_state) = query_button_action.get_single() {
+        if action_state.just_pressed(&UiButtonAction::Three) {
+            game_state.set(GameState::Settings);
+        }
+    }
+}
+
+// There is nothing to do between encounters yet, so each one ends back at the menu
+fn evr_encounter_ended(
+    mut game_state: ResMut<NextState<GameState>>,
+    mut evr_encounter_ended: EventReader<EncounterEnded>,
+) {
+    for ev in evr_encounter_ended.read() {
+        info!("[ENDED] Encounter: {:?}", ev.outcome);
+        game_state.set(GameState::Menu);
+    }
+}
+
 fn cleanup(mut commands: Commands, menu: Query<Entity, With<CleanupMainMenu>>) {
     for entity in menu.iter() {
         commands.entity(entity).despawn_recursive();
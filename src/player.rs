@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+use crate::character::StatSheet;
 use crate::GameState;
 
 pub struct PlayerPlugin;
@@ -18,7 +19,7 @@ impl Plugin for PlayerPlugin {
 fn startup() {}
 
 fn spawn_player(mut commands: Commands) {
-    commands.spawn(Player);
+    commands.spawn((Player, StatSheet::default()));
     info!("[SPAWNED] Player");
 }
 
@@ -1,7 +1,18 @@
+use bevy::asset::ron;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::GameState;
+use bevy::window::{Monitor, PrimaryMonitor, WindowMode};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::actions::{
+    key_label, ActionBinding, BindingInput, Bindings, NavigationAction, UiButtonAction,
+};
+use crate::audio::{MetronomeOffset, NoteKind};
+use crate::loading::UiAssets;
+use crate::storage;
+use crate::ui::{Palette, UiBackgroundColor, UiBorderColor, UiTextColor};
+use crate::{GameState, SettingsState};
 
 pub struct SettingsPlugin;
 impl Plugin for SettingsPlugin {
@@ -10,51 +21,1046 @@ impl Plugin for SettingsPlugin {
     }
 
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup)
-            .add_systems(OnEnter(GameState::Settings), on_enter)
-            .insert_resource(Settings::default());
+        app.add_systems(OnEnter(SettingsState::Main), on_enter)
+            .add_systems(
+                Update,
+                (
+                    (
+                        capture_binding,
+                        navigate_settings,
+                        click_settings_rows,
+                        click_settings_arrows,
+                        update_settings_rows,
+                    )
+                        .chain()
+                        .run_if(in_state(SettingsState::Main)),
+                    apply_window,
+                    apply_latency.run_if(resource_changed::<Settings>),
+                    save_settings
+                        .run_if(resource_changed::<Settings>.and(not(resource_added::<Settings>))),
+                ),
+            )
+            .add_systems(OnExit(SettingsState::Main), cleanup)
+            .init_resource::<SettingsFocus>()
+            .init_resource::<Rebinding>()
+            .insert_resource(Settings::load());
     }
 }
 
-#[derive(Resource, Clone, Default, Serialize, Deserialize)]
+// Fields missing from an older file take their default value
+#[derive(Resource, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub resolution: Resolution,
     pub monitor: Option<usize>,
+    pub volume: Volume,
+    pub click: ClickTrack,
+    pub latency: Latency,
+    pub bindings: Bindings,
+}
+impl Settings {
+    const FILE: &'static str = "settings.ron";
+    // Bump when a change can't be read by `#[serde(default)]` alone. Version 1 stored the master
+    // volume as a bare level, which `ChannelVolume` still reads.
+    const VERSION: u32 = 2;
+
+    // Falls back to the defaults when there is no file yet, or it can't be understood
+    fn load() -> Self {
+        let Some(contents) = storage::read(Self::FILE) else {
+            return Self::default();
+        };
+        match Self::parse(&contents) {
+            Ok(settings) => {
+                info!("[LOADED] Settings");
+                settings
+            }
+            Err(error) => {
+                warn!("Failed to read settings, using defaults {error:?}");
+                Self::default()
+            }
+        }
+    }
+
+    // Files from older versions are read as the current one, newer ones are refused
+    fn parse(contents: &str) -> Result<Self, String> {
+        let header =
+            ron::from_str::<SettingsVersion>(contents).map_err(|error| error.to_string())?;
+        if header.version > Self::VERSION {
+            return Err(format!("version {} is not supported", header.version));
+        }
+        ron::from_str::<SettingsFile>(contents)
+            .map(|file| file.settings)
+            .map_err(|error| error.to_string())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let file = SettingsFile {
+            version: Self::VERSION,
+            settings: self.clone(),
+        };
+        let contents = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())?;
+        storage::write(Self::FILE, &contents)
+    }
+}
+
+// Layout of the saved settings, versioned so old files can be told apart
+#[derive(Serialize, Deserialize)]
+struct SettingsFile {
+    version: u32,
+    settings: Settings,
+}
+
+#[derive(Deserialize)]
+struct SettingsVersion {
+    version: u32,
+}
+
+// Every channel is scaled by `master` as well as its own volume
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Volume {
+    pub master: ChannelVolume,
+    pub music: ChannelVolume,
+    pub sfx: ChannelVolume,
+    pub click: ChannelVolume,
+    pub ui: ChannelVolume,
+}
+impl Volume {
+    pub fn bus(&self, bus: AudioBus) -> &ChannelVolume {
+        match bus {
+            AudioBus::Master => &self.master,
+            AudioBus::Music => &self.music,
+            AudioBus::Sfx => &self.sfx,
+            AudioBus::Click => &self.click,
+            AudioBus::Ui => &self.ui,
+        }
+    }
+
+    pub fn bus_mut(&mut self, bus: AudioBus) -> &mut ChannelVolume {
+        match bus {
+            AudioBus::Master => &mut self.master,
+            AudioBus::Music => &mut self.music,
+            AudioBus::Sfx => &mut self.sfx,
+            AudioBus::Click => &mut self.click,
+            AudioBus::Ui => &mut self.ui,
+        }
+    }
+
+    // Volume a channel actually plays at
+    pub fn gain(&self, bus: AudioBus) -> f32 {
+        self.master.gain() * self.bus(bus).gain()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioBus {
+    Master,
+    Music,
+    Sfx,
+    Click,
+    Ui,
+}
+
+// Muting keeps `level` so unmuting goes back to it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ChannelVolumeFile")]
+pub struct ChannelVolume {
+    pub level: f32,
+    pub muted: bool,
+}
+
+// A bare level is how version 1 stored the master volume
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChannelVolumeFile {
+    Level(f32),
+    Channel {
+        level: f32,
+        #[serde(default)]
+        muted: bool,
+    },
+}
+impl From<ChannelVolumeFile> for ChannelVolume {
+    fn from(file: ChannelVolumeFile) -> Self {
+        match file {
+            ChannelVolumeFile::Level(level) => Self {
+                level,
+                muted: false,
+            },
+            ChannelVolumeFile::Channel { level, muted } => Self { level, muted },
+        }
+    }
+}
+impl Default for ChannelVolume {
+    fn default() -> Self {
+        Self {
+            level: 1.0,
+            muted: false,
+        }
+    }
+}
+impl ChannelVolume {
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.level
+        }
+    }
+
+    // Stepping down past silence mutes, stepping up again unmutes
+    pub fn step(&mut self, step: i32) {
+        if self.muted {
+            self.muted = step < 0;
+            return;
+        }
+        if step < 0 && self.level <= 0.0 {
+            self.muted = true;
+            return;
+        }
+        let level = self.level + step as f32 * VOLUME_STEP;
+        self.level = (level * 10.0).round().clamp(0.0, 10.0) / 10.0;
+    }
 }
 
-#[derive(Clone, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClickTrack {
+    pub enabled: bool,
+    pub subdivision: ClickSubdivision,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClickSubdivision {
+    #[default]
+    Quarter,
+    Eighth,
+    Sixteenth,
+}
+impl ClickSubdivision {
+    pub fn kind(&self) -> NoteKind {
+        match self {
+            ClickSubdivision::Quarter => NoteKind::Quarter,
+            ClickSubdivision::Eighth => NoteKind::Eighth,
+            ClickSubdivision::Sixteenth => NoteKind::Sixteenth,
+        }
+    }
+
+    pub fn cycle(&self, step: i32) -> Self {
+        let all = [
+            ClickSubdivision::Quarter,
+            ClickSubdivision::Eighth,
+            ClickSubdivision::Sixteenth,
+        ];
+        let index = all
+            .iter()
+            .position(|subdivision| subdivision == self)
+            .unwrap_or(0);
+        all[(index as i32 + step).rem_euclid(all.len() as i32) as usize]
+    }
+}
+
+// Milliseconds between a beat and the player pressing on it, measured by calibration. `audio`
+// is timed against the click track, `visual` against a flash with the audio muted.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub audio: f32,
+    pub visual: f32,
+}
+
+// `vec` is the native size of the game in pixels, the window is an integer multiple of it
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Resolution {
     pub vec: Vec2,
     pub scale: ScaleFactor,
+    pub mode: DisplayMode,
+}
+impl Default for Resolution {
+    fn default() -> Self {
+        Self {
+            vec: Vec2::new(640., 360.),
+            scale: ScaleFactor::default(),
+            mode: DisplayMode::default(),
+        }
+    }
 }
 
-#[derive(Clone, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ScaleFactor {
-    Large,
-    #[default]
-    Small,
+    X1,
+    X2,
+    #[serde(alias = "Small")]
+    X3,
+    #[serde(alias = "Large")]
+    X4,
+    X5,
+    X6,
+    // Largest integer scale that fits on the monitor
+    Fit,
+}
+impl Default for ScaleFactor {
+    fn default() -> Self {
+        if cfg!(any(target_os = "android", target_os = "ios")) {
+            ScaleFactor::Fit
+        } else {
+            ScaleFactor::X3
+        }
+    }
 }
 impl ScaleFactor {
-    pub fn scale(&self) -> f32 {
+    const ALL: [ScaleFactor; 7] = [
+        ScaleFactor::X1,
+        ScaleFactor::X2,
+        ScaleFactor::X3,
+        ScaleFactor::X4,
+        ScaleFactor::X5,
+        ScaleFactor::X6,
+        ScaleFactor::Fit,
+    ];
+
+    // Scale of `native` pixels, `available` is the logical size of the monitor
+    pub fn scale(&self, native: Vec2, available: Vec2) -> f32 {
         match self {
-            ScaleFactor::Large => 4.,
-            ScaleFactor::Small => 3.,
+            ScaleFactor::X1 => 1.,
+            ScaleFactor::X2 => 2.,
+            ScaleFactor::X3 => 3.,
+            ScaleFactor::X4 => 4.,
+            ScaleFactor::X5 => 5.,
+            ScaleFactor::X6 => 6.,
+            ScaleFactor::Fit => (available / native).min_element().floor().max(1.),
         }
     }
+
+    pub fn label(&self) -> String {
+        match self {
+            ScaleFactor::Fit => "Fit".to_string(),
+            scale => format!("{}x", scale.scale(Vec2::ONE, Vec2::ONE)),
+        }
+    }
+
+    pub fn cycle(&self, step: i32) -> Self {
+        let all = Self::ALL;
+        let index = all.iter().position(|scale| scale == self).unwrap_or(0);
+        all[(index as i32 + step).rem_euclid(all.len() as i32) as usize]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisplayMode {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+impl Default for DisplayMode {
+    fn default() -> Self {
+        if cfg!(any(target_os = "android", target_os = "ios")) {
+            DisplayMode::Borderless
+        } else {
+            DisplayMode::Windowed
+        }
+    }
+}
+impl DisplayMode {
+    pub fn cycle(&self, step: i32) -> Self {
+        let all = [
+            DisplayMode::Windowed,
+            DisplayMode::Borderless,
+            DisplayMode::Fullscreen,
+        ];
+        let index = all.iter().position(|mode| mode == self).unwrap_or(0);
+        all[(index as i32 + step).rem_euclid(all.len() as i32) as usize]
+    }
 }
 
-fn startup(settings: Res<Settings>, mut query_window: Query<&mut Window>) {
-    if let Ok(mut window) = query_window.get_single_mut() {
-        window.resolution.set(
-            640. * settings.resolution.scale.scale(),
-            360. * settings.resolution.scale.scale(),
+// Rows of the settings screen, top to bottom
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum SettingsRow {
+    Scale,
+    Mode,
+    Monitor,
+    Volume(AudioBus),
+    Click,
+    Subdivision,
+    AudioLatency,
+    VisualLatency,
+    Binding(UiButtonAction),
+    Calibrate,
+    Back,
+}
+impl SettingsRow {
+    const ALL: [SettingsRow; 18] = [
+        SettingsRow::Scale,
+        SettingsRow::Mode,
+        SettingsRow::Monitor,
+        SettingsRow::Volume(AudioBus::Master),
+        SettingsRow::Volume(AudioBus::Music),
+        SettingsRow::Volume(AudioBus::Sfx),
+        SettingsRow::Volume(AudioBus::Click),
+        SettingsRow::Volume(AudioBus::Ui),
+        SettingsRow::Click,
+        SettingsRow::Subdivision,
+        SettingsRow::AudioLatency,
+        SettingsRow::VisualLatency,
+        SettingsRow::Binding(UiButtonAction::One),
+        SettingsRow::Binding(UiButtonAction::Two),
+        SettingsRow::Binding(UiButtonAction::Three),
+        SettingsRow::Binding(UiButtonAction::Four),
+        SettingsRow::Calibrate,
+        SettingsRow::Back,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            SettingsRow::Scale => "Scale",
+            SettingsRow::Mode => "Display",
+            SettingsRow::Monitor => "Monitor",
+            SettingsRow::Volume(AudioBus::Master) => "Volume",
+            SettingsRow::Volume(AudioBus::Music) => "Music",
+            SettingsRow::Volume(AudioBus::Sfx) => "Effects",
+            SettingsRow::Volume(AudioBus::Click) => "Click",
+            SettingsRow::Volume(AudioBus::Ui) => "Interface",
+            SettingsRow::Click => "Click Track",
+            SettingsRow::Subdivision => "Click Every",
+            SettingsRow::AudioLatency => "Audio Offset",
+            SettingsRow::VisualLatency => "Visual Offset",
+            SettingsRow::Binding(UiButtonAction::One) => "Button 1",
+            SettingsRow::Binding(UiButtonAction::Two) => "Button 2",
+            SettingsRow::Binding(UiButtonAction::Three) => "Button 3",
+            SettingsRow::Binding(UiButtonAction::Four) => "Button 4",
+            SettingsRow::Calibrate => "Calibrate Latency",
+            SettingsRow::Back => "Back",
+        }
+    }
+
+    // Rows that are pressed rather than stepped through values
+    fn is_action(&self) -> bool {
+        matches!(
+            self,
+            SettingsRow::Binding(_) | SettingsRow::Calibrate | SettingsRow::Back
+        )
+    }
+
+    fn value(&self, settings: &Settings) -> String {
+        match self {
+            SettingsRow::Scale => settings.resolution.scale.label(),
+            SettingsRow::Mode => format!("{:?}", settings.resolution.mode),
+            SettingsRow::Monitor => settings
+                .monitor
+                .map_or("Primary".to_string(), |index| format!("{}", index + 1)),
+            SettingsRow::Volume(bus) => match settings.volume.bus(*bus) {
+                volume if volume.muted => "Muted".to_string(),
+                volume => format!("{:.0}%", volume.level * 100.0),
+            },
+            SettingsRow::Click => if settings.click.enabled { "On" } else { "Off" }.to_string(),
+            SettingsRow::Subdivision => format!("{:?}", settings.click.subdivision),
+            SettingsRow::AudioLatency => format!("{:+.0}ms", settings.latency.audio),
+            SettingsRow::VisualLatency => format!("{:+.0}ms", settings.latency.visual),
+            SettingsRow::Binding(action) => settings
+                .bindings
+                .get(*action)
+                .map_or(String::new(), ActionBinding::label),
+            SettingsRow::Calibrate | SettingsRow::Back => String::new(),
+        }
+    }
+}
+
+// Index into `SettingsRow::ALL` of the row the keys act on
+#[derive(Resource, Default, PartialEq, Deref, DerefMut)]
+struct SettingsFocus(usize);
+
+#[derive(Component)]
+struct SettingsValue;
+
+#[derive(Component)]
+struct SettingsHint;
+
+// The action waiting for its next key or gamepad button, the action that gave up the last
+// captured input to it and the last key refused because the menus use it
+#[derive(Resource, Default)]
+struct Rebinding {
+    action: Option<UiButtonAction>,
+    swapped: Option<UiButtonAction>,
+    refused: Option<KeyCode>,
+}
+impl Rebinding {
+    fn hint(&self, settings: &Settings) -> String {
+        if let (Some(_), Some(key)) = (self.action, self.refused) {
+            return format!("{} is used by the menus, press another", key_label(key));
+        }
+        if self.action.is_some() {
+            return "Press a key or button, Esc to cancel".to_string();
+        }
+        if let Some(swapped) = self.swapped {
+            return format!("Swapped with {}", SettingsRow::Binding(swapped).label());
+        }
+        let [one, two, three, four] = UiButtonAction::array().map(|action| {
+            settings
+                .bindings
+                .get(action)
+                .and_then(|binding| binding.keys.first())
+                .map_or(String::new(), |key| key_label(*key))
+        });
+        format!("{one}/{two} or Up/Down: Select   {three}/{four} or Left/Right: Change")
+    }
+}
+
+#[derive(Component)]
+struct SettingsArrow {
+    row: SettingsRow,
+    step: i32,
+}
+
+#[derive(Component)]
+struct CleanupSettings;
+
+const LATENCY_STEP: f32 = 5.0;
+const VOLUME_STEP: f32 = 0.1;
+
+// Steps the value of `row`, or presses it when it is an action row
+fn change_setting(
+    row: SettingsRow,
+    step: i32,
+    settings: &mut Settings,
+    monitors: usize,
+    rebinding: &mut Rebinding,
+    game_state: &mut NextState<GameState>,
+    settings_state: &mut NextState<SettingsState>,
+) {
+    match row {
+        SettingsRow::Scale => {
+            settings.resolution.scale = settings.resolution.scale.cycle(step);
+        }
+        SettingsRow::Mode => {
+            settings.resolution.mode = settings.resolution.mode.cycle(step);
+        }
+        SettingsRow::Monitor => {
+            // Primary first, then every connected monitor
+            let count = monitors as i32 + 1;
+            let index = settings.monitor.map_or(0, |index| index as i32 + 1);
+            settings.monitor = match (index + step).rem_euclid(count) {
+                0 => None,
+                index => Some(index as usize - 1),
+            };
+        }
+        SettingsRow::Volume(bus) => settings.volume.bus_mut(bus).step(step),
+        SettingsRow::Click => settings.click.enabled = !settings.click.enabled,
+        SettingsRow::Subdivision => {
+            settings.click.subdivision = settings.click.subdivision.cycle(step);
+        }
+        SettingsRow::AudioLatency => {
+            settings.latency.audio += step as f32 * LATENCY_STEP;
+        }
+        SettingsRow::VisualLatency => {
+            settings.latency.visual += step as f32 * LATENCY_STEP;
+        }
+        SettingsRow::Binding(action) => {
+            *rebinding = Rebinding {
+                action: Some(action),
+                ..default()
+            };
+        }
+        SettingsRow::Calibrate => settings_state.set(SettingsState::Calibration),
+        SettingsRow::Back => game_state.set(GameState::Menu),
+    }
+}
+
+// SYSTEMS
+
+// Resizes, recentres and changes the mode of the window. Scaling the game into it is left to the
+// `Canvas`. Only runs when the window settings differ from the ones last applied, so changing
+// anything else doesn't move the window back.
+fn apply_window(
+    settings: Res<Settings>,
+    mut applied: Local<Option<(Resolution, Option<usize>)>>,
+    mut query_window: Query<&mut Window>,
+    query_monitor: Query<(Entity, &Monitor, Has<PrimaryMonitor>)>,
+    query_new_monitor: Query<(), Added<Monitor>>,
+) {
+    // Monitors only show up after startup, and `Fit` depends on them
+    let window_settings = (settings.resolution.clone(), settings.monitor);
+    if applied.as_ref() == Some(&window_settings) && query_new_monitor.is_empty() {
+        return;
+    }
+    let Ok(mut window) = query_window.get_single_mut() else {
+        return;
+    };
+    let resolution = &settings.resolution;
+    // Monitors are numbered in the order they were found, and selected by entity so the one
+    // sized against is the one the window goes to
+    let mut monitors = query_monitor.iter().collect::<Vec<_>>();
+    monitors.sort_by_key(|(entity, ..)| *entity);
+    let monitor = match settings.monitor {
+        Some(index) => monitors.get(index).copied(),
+        None => monitors.iter().find(|(_, _, primary)| *primary).copied(),
+    };
+    let selection = monitor.map_or(MonitorSelection::Primary, |(entity, ..)| {
+        MonitorSelection::Entity(entity)
+    });
+    // Logical size of the monitor, or just enough for the native size when it's unknown
+    let available = monitor.map_or(resolution.vec, |(_, monitor, _)| {
+        Vec2::new(
+            monitor.physical_width as f32,
+            monitor.physical_height as f32,
+        ) / monitor.scale_factor as f32
+    });
+    let scale = resolution.scale.scale(resolution.vec, available);
+
+    window.mode = match resolution.mode {
+        DisplayMode::Windowed => WindowMode::Windowed,
+        DisplayMode::Borderless => WindowMode::BorderlessFullscreen(selection),
+        DisplayMode::Fullscreen => WindowMode::Fullscreen(selection),
+    };
+    if resolution.mode == DisplayMode::Windowed {
+        let size = resolution.vec * scale;
+        window.resolution.set(size.x, size.y);
+        window.position.center(selection);
+    }
+    info!("[APPLIED] Window: {:?} at {scale}x", resolution.mode);
+    *applied = Some(window_settings);
+}
+
+fn on_enter(
+    mut commands: Commands,
+    ui: Res<UiAssets>,
+    settings: Res<Settings>,
+    mut focus: ResMut<SettingsFocus>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    **focus = 0;
+    *rebinding = Rebinding::default();
+    let style = (
+        BackgroundColor(UiBackgroundColor::default().normal.srgb()),
+        BorderColor(UiBorderColor::default().normal.srgb()),
+        BorderRadius::ZERO,
+    );
+    let text_font = TextFont {
+        font: ui.pixelify.clone(),
+        font_size: 6.0,
+        ..default()
+    };
+    let text_color = TextColor(UiTextColor::default().normal.srgb());
+
+    commands
+        .spawn((
+            Name::new("Settings Parent Node"),
+            Node {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(2.),
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            // Covers the menu HUD
+            BackgroundColor(Palette::Darker.srgb()),
+            GlobalZIndex(1),
+            CleanupSettings,
+        ))
+        .with_children(|children| {
+            for row in SettingsRow::ALL {
+                children
+                    .spawn((
+                        Name::new(format!("Settings Row: {row:?}")),
+                        Node {
+                            width: Val::Px(160.),
+                            height: Val::Px(14.),
+                            flex_direction: FlexDirection::Row,
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::SpaceBetween,
+                            padding: UiRect::horizontal(Val::Px(3.)),
+                            ..default()
+                        },
+                        BackgroundColor(UiBackgroundColor::default().normal.srgb()),
+                        Interaction::default(),
+                        row,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((Text::new(row.label()), text_font.clone(), text_color));
+                        if row.is_action() {
+                            parent.spawn((
+                                Text::new(row.value(&settings)),
+                                text_font.clone(),
+                                text_color,
+                                SettingsValue,
+                            ));
+                            return;
+                        }
+                        parent
+                            .spawn(Node {
+                                align_items: AlignItems::Center,
+                                column_gap: Val::Px(2.),
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                for (step, arrow) in [(-1, "<"), (1, ">")] {
+                                    if step == 1 {
+                                        parent.spawn((
+                                            Text::new(row.value(&settings)),
+                                            text_font.clone(),
+                                            text_color,
+                                            SettingsValue,
+                                        ));
+                                    }
+                                    parent
+                                        .spawn((
+                                            Name::new(format!("Settings Arrow: {arrow}")),
+                                            Button,
+                                            SettingsArrow { row, step },
+                                            Node {
+                                                width: Val::Px(10.),
+                                                height: Val::Px(10.),
+                                                justify_content: JustifyContent::Center,
+                                                align_items: AlignItems::Center,
+                                                ..default()
+                                            },
+                                            style,
+                                        ))
+                                        .with_child((
+                                            Text::new(arrow),
+                                            text_font.clone(),
+                                            text_color,
+                                        ));
+                                }
+                            });
+                    });
+            }
+            children.spawn((
+                Text::new(Rebinding::default().hint(&settings)),
+                text_font.clone(),
+                TextColor(Palette::Lighter.srgb()),
+                SettingsHint,
+            ));
+        });
+    info!("[SPAWNED] Settings");
+}
+
+// Binds the next key or gamepad button pressed to the action being rebound
+fn capture_binding(
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+    keys: Res<ButtonInput<KeyCode>>,
+    query_gamepad: Query<&Gamepad>,
+) {
+    let Some(action) = rebinding.action else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::Escape) {
+        rebinding.action = None;
+        return;
+    }
+    let key = keys
+        .get_just_pressed()
+        .next()
+        .copied()
+        .map(BindingInput::Key);
+    let button = query_gamepad
+        .iter()
+        .find_map(|gamepad| gamepad.get_just_pressed().next().copied())
+        .map(BindingInput::Button);
+    let Some(input) = key.or(button) else {
+        return;
+    };
+    // A refused key leaves the settings untouched and keeps waiting
+    let mut bindings = settings.bindings.clone();
+    match bindings.rebind(action, input) {
+        Ok(swapped) => {
+            settings.bindings = bindings;
+            *rebinding = Rebinding {
+                swapped,
+                ..default()
+            };
+            info!("[REBOUND] {action:?}: {input:?}");
+        }
+        Err(key) => rebinding.refused = Some(key),
+    }
+}
+
+// Either the keys of the four rhythm buttons (up, down, decrease, increase) or the navigation
+// actions. On a gamepad the rhythm buttons share face buttons with Confirm and Back, so there
+// only the navigation actions count.
+#[allow(clippy::too_many_arguments)]
+fn navigate_settings(
+    mut focus: ResMut<SettingsFocus>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    keys: Res<ButtonInput<KeyCode>>,
+    query_monitor: Query<&Monitor>,
+    query_navigation: Query<&ActionState<NavigationAction>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut settings_state: ResMut<NextState<SettingsState>>,
+) {
+    // The press that was just captured shouldn't also navigate
+    if rebinding.action.is_some() || rebinding.is_changed() {
+        return;
+    }
+    let pressed = |navigation: NavigationAction| {
+        query_navigation
+            .iter()
+            .any(|action_state| action_state.just_pressed(&navigation))
+    };
+    let key_pressed = |button: UiButtonAction| {
+        settings
+            .bindings
+            .get(button)
+            .is_some_and(|binding| keys.any_just_pressed(binding.keys.iter().copied()))
+    };
+    if pressed(NavigationAction::Back) {
+        game_state.set(GameState::Menu);
+        return;
+    }
+    let [up, down, left, right] = [
+        (UiButtonAction::One, NavigationAction::Up),
+        (UiButtonAction::Two, NavigationAction::Down),
+        (UiButtonAction::Three, NavigationAction::Left),
+        (UiButtonAction::Four, NavigationAction::Right),
+    ]
+    .map(|(button, navigation)| key_pressed(button) || pressed(navigation));
+    let confirm = pressed(NavigationAction::Confirm);
+    let rows = SettingsRow::ALL.len();
+    if up {
+        **focus = (**focus + rows - 1) % rows;
+    }
+    if down {
+        **focus = (**focus + 1) % rows;
+    }
+    let row = SettingsRow::ALL[**focus];
+    if confirm {
+        if let SettingsRow::Volume(bus) = row {
+            let volume = settings.volume.bus_mut(bus);
+            volume.muted = !volume.muted;
+            return;
+        }
+    }
+    let step = match (left, right || confirm) {
+        (true, false) => -1,
+        (false, true) => 1,
+        _ => return,
+    };
+    // Action rows and values that can't step further leave the settings untouched
+    let mut changed = settings.clone();
+    change_setting(
+        row,
+        step,
+        &mut changed,
+        query_monitor.iter().count(),
+        &mut rebinding,
+        &mut game_state,
+        &mut settings_state,
+    );
+    settings.set_if_neq(changed);
+}
+
+fn click_settings_rows(
+    mut focus: ResMut<SettingsFocus>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    query_monitor: Query<&Monitor>,
+    query_row: Query<(&Interaction, &SettingsRow), Changed<Interaction>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut settings_state: ResMut<NextState<SettingsState>>,
+) {
+    if rebinding.action.is_some() {
+        return;
+    }
+    for (interaction, row) in &query_row {
+        if *interaction == Interaction::None {
+            continue;
+        }
+        if let Some(index) = SettingsRow::ALL.iter().position(|r| r == row) {
+            focus.set_if_neq(SettingsFocus(index));
+        }
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let SettingsRow::Volume(bus) = row {
+            let volume = settings.volume.bus_mut(*bus);
+            volume.muted = !volume.muted;
+        } else if row.is_action() {
+            let mut changed = settings.clone();
+            change_setting(
+                *row,
+                1,
+                &mut changed,
+                query_monitor.iter().count(),
+                &mut rebinding,
+                &mut game_state,
+                &mut settings_state,
+            );
+            settings.set_if_neq(changed);
+        }
+    }
+}
+
+fn click_settings_arrows(
+    mut focus: ResMut<SettingsFocus>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+    query_monitor: Query<&Monitor>,
+    query_arrow: Query<(&Interaction, &SettingsArrow), (Changed<Interaction>, With<Button>)>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut settings_state: ResMut<NextState<SettingsState>>,
+) {
+    if rebinding.action.is_some() {
+        return;
+    }
+    for (interaction, arrow) in &query_arrow {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(index) = SettingsRow::ALL.iter().position(|r| *r == arrow.row) {
+            **focus = index;
+        }
+        let mut changed = settings.clone();
+        change_setting(
+            arrow.row,
+            arrow.step,
+            &mut changed,
+            query_monitor.iter().count(),
+            &mut rebinding,
+            &mut game_state,
+            &mut settings_state,
         );
-        if let Some(u) = settings.monitor {
-            window.position.center(MonitorSelection::Index(u));
+        settings.set_if_neq(changed);
+    }
+}
+
+fn update_settings_rows(
+    focus: Res<SettingsFocus>,
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+    mut query_row: Query<(&SettingsRow, &mut BackgroundColor, &Children)>,
+    query_children: Query<&Children>,
+    mut query_value: Query<&mut Text, (With<SettingsValue>, Without<SettingsHint>)>,
+    mut query_hint: Query<&mut Text, With<SettingsHint>>,
+) {
+    if !focus.is_changed() && !settings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for mut text in &mut query_hint {
+        text.0 = rebinding.hint(&settings);
+    }
+    for (row, mut background_color, children) in &mut query_row {
+        let focused = SettingsRow::ALL.get(**focus) == Some(row);
+        background_color.0 = if focused {
+            UiBackgroundColor::default().hovered.srgb()
         } else {
-            window.position.center(MonitorSelection::Primary);
+            UiBackgroundColor::default().normal.srgb()
+        };
+        // The value sits either directly in the row or next to its arrows
+        let descendants = children.iter().flat_map(|child| {
+            std::iter::once(*child).chain(query_children.iter_descendants(*child))
+        });
+        for entity in descendants {
+            if let Ok(mut text) = query_value.get_mut(entity) {
+                text.0 = match rebinding.action {
+                    Some(action) if *row == SettingsRow::Binding(action) => "...".to_string(),
+                    _ => row.value(&settings),
+                };
+            }
         }
     }
 }
 
-fn on_enter() {}
+fn apply_latency(settings: Res<Settings>, mut metronome_offset: ResMut<MetronomeOffset>) {
+    metronome_offset.audio = settings.latency.audio / 1000.0;
+    metronome_offset.visual = settings.latency.visual / 1000.0;
+}
+
+fn save_settings(settings: Res<Settings>) {
+    match settings.save() {
+        Ok(()) => info!("[SAVED] Settings"),
+        Err(error) => warn!("Failed to save settings {error:?}"),
+    }
+}
+
+fn cleanup(mut commands: Commands, query_cleanup: Query<Entity, With<CleanupSettings>>) {
+    for entity in query_cleanup.iter() {
+        commands.entity(entity).despawn_recursive();
+        info!("[CLEANUP] Settings");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_version_one_files() {
+        let contents = "(version: 1, settings: (monitor: Some(1), volume: (master: 0.5), \
+            latency: (audio: 20.0, visual: 10.0)))";
+        let settings = Settings::parse(contents).unwrap();
+        assert_eq!(settings.monitor, Some(1));
+        assert_eq!(settings.volume.master.level, 0.5);
+        assert!(!settings.volume.master.muted);
+        assert_eq!(settings.volume.music, ChannelVolume::default());
+        assert_eq!(settings.latency.audio, 20.0);
+    }
+
+    #[test]
+    fn migrates_bare_volume_levels() {
+        // Saved by version 1, before the mixer had channels
+        let contents = "(
+    version: 1,
+    settings: (
+        resolution: (vec: (640.0, 360.0), scale: X4, mode: Borderless),
+        monitor: None,
+        volume: (master: 0.7),
+        latency: (audio: -15.0, visual: 25.0),
+    ),
+)";
+        let file: SettingsFile = ron::from_str(contents).unwrap();
+        assert_eq!(file.version, 1);
+        let settings = file.settings;
+        assert_eq!(
+            settings.volume.master,
+            ChannelVolume {
+                level: 0.7,
+                muted: false,
+            }
+        );
+        for bus in [
+            AudioBus::Music,
+            AudioBus::Sfx,
+            AudioBus::Click,
+            AudioBus::Ui,
+        ] {
+            assert_eq!(*settings.volume.bus(bus), ChannelVolume::default());
+        }
+        assert_eq!(settings.resolution.scale, ScaleFactor::X4);
+        assert_eq!(settings.resolution.mode, DisplayMode::Borderless);
+        assert_eq!(
+            settings.latency,
+            Latency {
+                audio: -15.0,
+                visual: 25.0,
+            }
+        );
+        assert_eq!(settings.bindings, Bindings::default());
+    }
+
+    #[test]
+    fn round_trips_the_current_version() {
+        let mut settings = Settings::default();
+        settings.volume.sfx = ChannelVolume {
+            level: 0.3,
+            muted: true,
+        };
+        settings.click.enabled = true;
+        let file = SettingsFile {
+            version: Settings::VERSION,
+            settings: settings.clone(),
+        };
+        let contents = ron::ser::to_string(&file).unwrap();
+        assert!(Settings::parse(&contents).unwrap() == settings);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let contents = format!("(version: {}, settings: ())", Settings::VERSION + 1);
+        assert!(Settings::parse(&contents).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{ron, AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_kira_audio::AudioSource;
+use serde::Deserialize;
+
+use crate::audio::{AudioInfo, AudioLength, Metre, Tempo};
+use crate::chart::{ChartAsset, Difficulty};
+use crate::loader::RonLoaderError;
+
+pub struct SongPlugin;
+impl Plugin for SongPlugin {
+    fn name(&self) -> &str {
+        "Song Plugin"
+    }
+
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SongAsset>()
+            .init_asset_loader::<SongLoader>();
+    }
+}
+
+// DATA
+
+#[derive(Asset, TypePath)]
+pub struct SongAsset {
+    pub title: String,
+    pub audio: Handle<AudioSource>,
+    pub info: AudioInfo,
+    pub changes: Vec<TempoChange>,
+    pub charts: HashMap<Difficulty, Handle<ChartAsset>>,
+}
+
+// On disk layout of a `.song` file, e.g.
+// (
+//     title: "Demo",
+//     audio: "audio/songs/demo.wav",
+//     tempo: 120.0,
+//     metre: (4, 4),
+//     intro: Some(2.0),
+//     body: 4.0,
+//     outro: Some(2.0),
+//     changes: [
+//         (measure: 4, metre: Some((7, 8))),
+//         (measure: 5, metre: Some((4, 4))),
+//         (measure: 8, tempo: Some(140.0), ramp: true),
+//     ],
+//     charts: {Normal: "audio/songs/demo.normal.chart"},
+// )
+// Section lengths are in seconds, `audio` and `charts` are relative to the assets folder.
+#[derive(Deserialize)]
+struct SongFile {
+    title: String,
+    audio: String,
+    tempo: f32,
+    metre: (u8, u8),
+    #[serde(default)]
+    intro: Option<f32>,
+    body: f32,
+    #[serde(default)]
+    outro: Option<f32>,
+    #[serde(default)]
+    changes: Vec<TempoChange>,
+    #[serde(default)]
+    charts: HashMap<Difficulty, String>,
+}
+
+// Change of tempo and/or metre starting on the downbeat of `measure`. With `ramp` the tempo
+// glides linearly from the previous change and only arrives at `tempo` on that downbeat.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct TempoChange {
+    pub measure: u32,
+    #[serde(default)]
+    pub tempo: Option<f32>,
+    #[serde(default)]
+    pub metre: Option<(u8, u8)>,
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+#[derive(Default)]
+pub struct SongLoader;
+impl AssetLoader for SongLoader {
+    type Asset = SongAsset;
+    type Settings = ();
+    type Error = RonLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file: SongFile = ron::de::from_bytes(&bytes)?;
+        file.validate()?;
+
+        Ok(SongAsset {
+            audio: load_context.load(file.audio),
+            info: AudioInfo {
+                tempo: Tempo(file.tempo),
+                metre: Metre::from(file.metre),
+                intro: file.intro.map(AudioLength),
+                body: AudioLength(file.body),
+                outro: file.outro.map(AudioLength),
+            },
+            charts: file
+                .charts
+                .into_iter()
+                .map(|(difficulty, path)| (difficulty, load_context.load(path)))
+                .collect(),
+            title: file.title,
+            changes: file.changes,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["song"]
+    }
+}
+
+impl SongFile {
+    fn validate(&self) -> Result<(), RonLoaderError> {
+        if self.tempo <= 0.0
+            || self
+                .changes
+                .iter()
+                .any(|c| c.tempo.is_some_and(|t| t <= 0.0))
+        {
+            return Err(RonLoaderError::Invalid("tempo must be positive"));
+        }
+        let valid_metre = |(top, bottom): (u8, u8)| top > 0 && bottom.is_power_of_two();
+        if !valid_metre(self.metre) || !self.changes.iter().filter_map(|c| c.metre).all(valid_metre)
+        {
+            return Err(RonLoaderError::Invalid(
+                "metre needs a non-zero top and a power of two bottom",
+            ));
+        }
+        let sections = [self.intro, Some(self.body), self.outro];
+        if sections.into_iter().flatten().any(|length| length < 0.0) {
+            return Err(RonLoaderError::Invalid("section lengths can't be negative"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+// Small text files kept between runs: the platform config directory on desktop, `localStorage`
+// on the web and the app's private data directory on mobile.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+#[cfg(not(any(target_arch = "wasm32", target_os = "android", target_os = "ios")))]
+fn directory() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "AwfullyMatt", "Chrysopoeia")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+#[cfg(target_os = "android")]
+fn directory() -> Option<PathBuf> {
+    bevy::window::ANDROID_APP.get()?.internal_data_path()
+}
+
+#[cfg(target_os = "ios")]
+fn directory() -> Option<PathBuf> {
+    // Everything under the app's home directory is private to it
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(name: &str) -> Option<String> {
+    std::fs::read_to_string(directory()?.join(name)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(name: &str, contents: &str) -> Result<(), String> {
+    let directory = directory().ok_or("No storage directory on this platform")?;
+    std::fs::create_dir_all(&directory).map_err(|error| error.to_string())?;
+    // Write next to the file first so a crash mid-write can't leave it truncated
+    let path = directory.join(name);
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, contents).map_err(|error| error.to_string())?;
+    std::fs::rename(&temporary, &path).map_err(|error| error.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read(name: &str) -> Option<String> {
+    local_storage()?.get_item(&key(name)).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write(name: &str, contents: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or("localStorage is not available")?
+        .set_item(&key(name), contents)
+        .map_err(|error| format!("{error:?}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn key(name: &str) -> String {
+    format!("chrysopoeia/{name}")
+}
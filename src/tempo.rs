@@ -0,0 +1,255 @@
+use crate::audio::{AudioInfo, Metre};
+use crate::song::TempoChange;
+
+// Guard against float error putting a note right on a boundary into the previous one
+pub const EPSILON: f32 = 1e-4;
+
+// Converts between song time in seconds and musical position in whole notes, following the
+// tempo and metre changes of a song. Positions are measured from the start of the audio file.
+#[derive(Clone, Default, Debug)]
+pub struct TempoMap {
+    segments: Vec<TempoSegment>,
+}
+
+// A run of measures sharing one metre. The tempo either stays constant or ramps linearly (per
+// beat) towards `end_tempo`, which is reached on the first downbeat of the next segment.
+#[derive(Clone, Copy, Debug)]
+struct TempoSegment {
+    measure: u32,
+    seconds: f32,
+    wholes: f32,
+    metre: Metre,
+    tempo: f32,
+    end_tempo: f32,
+    beats: f32, // Infinite for the last segment
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Measure {
+    pub index: u32,
+    pub start: f32,
+    pub length: f32,
+    pub metre: Metre,
+}
+
+impl TempoMap {
+    pub fn new(audio_info: &AudioInfo, changes: &[TempoChange]) -> Self {
+        if *audio_info.tempo <= 0.0 || audio_info.metre.measure() <= 0.0 {
+            return Self::default();
+        }
+        let mut changes = changes.to_vec();
+        changes.sort_by_key(|change| change.measure);
+
+        let mut segments = Vec::new();
+        let mut current = TempoSegment {
+            measure: 0,
+            seconds: 0.0,
+            wholes: 0.0,
+            metre: audio_info.metre,
+            tempo: *audio_info.tempo,
+            end_tempo: *audio_info.tempo,
+            beats: f32::INFINITY,
+        };
+        for change in changes {
+            // A change on the first measure of a segment replaces it outright
+            if change.measure == current.measure {
+                current.tempo = change.tempo.unwrap_or(current.tempo);
+                current.end_tempo = current.tempo;
+                current.metre = change.metre.map(Metre::from).unwrap_or(current.metre);
+                continue;
+            }
+            let measures = (change.measure - current.measure) as f32;
+            current.beats = measures * current.metre.beats();
+            if change.ramp {
+                current.end_tempo = change.tempo.unwrap_or(current.tempo);
+            }
+            let tempo = change.tempo.unwrap_or(current.end_tempo);
+            let next = TempoSegment {
+                measure: change.measure,
+                seconds: current.seconds + current.seconds_for(current.beats),
+                wholes: current.wholes + measures * current.metre.measure(),
+                metre: change.metre.map(Metre::from).unwrap_or(current.metre),
+                tempo,
+                end_tempo: tempo,
+                beats: f32::INFINITY,
+            };
+            segments.push(current);
+            current = next;
+        }
+        segments.push(current);
+
+        Self { segments }
+    }
+
+    // Musical position in whole notes at `seconds`
+    pub fn wholes(&self, seconds: f32) -> f32 {
+        let Some(segment) = self.segment_by(|segment| segment.seconds <= seconds) else {
+            return 0.0;
+        };
+        segment.wholes + segment.beats_for(seconds - segment.seconds) * segment.metre.beat()
+    }
+
+    // Song time in seconds at a musical position in whole notes
+    pub fn seconds(&self, wholes: f32) -> f32 {
+        let Some(segment) = self.segment_by(|segment| segment.wholes <= wholes) else {
+            return 0.0;
+        };
+        segment.seconds + segment.seconds_for((wholes - segment.wholes) / segment.metre.beat())
+    }
+
+    // The measure containing a musical position
+    pub fn measure(&self, wholes: f32) -> Option<Measure> {
+        let segment = self.segment_by(|segment| segment.wholes <= wholes)?;
+        let length = segment.metre.measure();
+        let count = ((wholes - segment.wholes) / length + EPSILON)
+            .floor()
+            .max(0.0);
+        Some(Measure {
+            index: segment.measure + count as u32,
+            start: segment.wholes + count * length,
+            length,
+            metre: segment.metre,
+        })
+    }
+
+    // The measure with the given index
+    pub fn measure_at_index(&self, index: u32) -> Option<Measure> {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.measure <= index)?;
+        let length = segment.metre.measure();
+        Some(Measure {
+            index,
+            start: segment.wholes + (index - segment.measure) as f32 * length,
+            length,
+            metre: segment.metre,
+        })
+    }
+
+    // Last segment starting at or before a position, or the first one for negative positions
+    fn segment_by(&self, starts_before: impl Fn(&TempoSegment) -> bool) -> Option<&TempoSegment> {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| starts_before(segment))
+            .or(self.segments.first())
+    }
+}
+
+impl TempoSegment {
+    // Change of tempo per beat
+    fn slope(&self) -> f32 {
+        if self.beats.is_finite() && self.beats > 0.0 {
+            (self.end_tempo - self.tempo) / self.beats
+        } else {
+            0.0
+        }
+    }
+
+    // Seconds taken by the first `beats` beats of the segment
+    fn seconds_for(&self, beats: f32) -> f32 {
+        let slope = self.slope();
+        if slope.abs() < f32::EPSILON {
+            beats * 60.0 / self.tempo
+        } else {
+            60.0 / slope * ((self.tempo + slope * beats) / self.tempo).ln()
+        }
+    }
+
+    // Beats played in the first `seconds` of the segment
+    fn beats_for(&self, seconds: f32) -> f32 {
+        let slope = self.slope();
+        if slope.abs() < f32::EPSILON {
+            seconds * self.tempo / 60.0
+        } else {
+            self.tempo * ((slope * seconds / 60.0).exp() - 1.0) / slope
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Tempo;
+    use bevy::prelude::default;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    fn tempo_map(tempo: f32, metre: (u8, u8), changes: &[TempoChange]) -> TempoMap {
+        let info = AudioInfo {
+            tempo: Tempo(tempo),
+            metre: Metre::from(metre),
+            ..default()
+        };
+        TempoMap::new(&info, changes)
+    }
+
+    fn change(
+        measure: u32,
+        tempo: Option<f32>,
+        metre: Option<(u8, u8)>,
+        ramp: bool,
+    ) -> TempoChange {
+        TempoChange {
+            measure,
+            tempo,
+            metre,
+            ramp,
+        }
+    }
+
+    #[test]
+    fn constant_tempo_converts_both_ways() {
+        let map = tempo_map(120.0, (4, 4), &[]);
+        assert_close(map.wholes(1.0), 0.5);
+        assert_close(map.seconds(2.0), 4.0);
+        assert_close(map.wholes(map.seconds(1.3)), 1.3);
+        let measure = map.measure(1.5).unwrap();
+        assert_eq!(measure.index, 1);
+        assert_close(measure.start, 1.0);
+        assert_close(measure.length, 1.0);
+    }
+
+    #[test]
+    fn ramp_integrates_across_measure_boundary() {
+        // 60 to 120 BPM over the first two measures, 7.5 BPM per beat
+        let map = tempo_map(60.0, (4, 4), &[change(2, Some(120.0), None, true)]);
+        let ramp_end = 8.0 * 2f32.ln();
+        assert_close(map.seconds(1.0), 8.0 * 1.5f32.ln());
+        assert_close(map.seconds(2.0), ramp_end);
+        assert_close(map.wholes(map.seconds(1.0)), 1.0);
+        assert_close(map.wholes(ramp_end), 2.0);
+        // Constant 120 BPM after the ramp
+        assert_close(map.seconds(3.0), ramp_end + 2.0);
+        assert_eq!(map.measure(1.5).unwrap().index, 1);
+        assert_eq!(map.measure(2.5).unwrap().index, 2);
+    }
+
+    #[test]
+    fn metre_change_from_four_four_to_seven_eight() {
+        let map = tempo_map(120.0, (4, 4), &[change(2, None, Some((7, 8)), false)]);
+        assert_close(map.seconds(2.0), 4.0);
+        // Eighth note beats at 120 BPM
+        assert_close(map.seconds(2.0 + 7.0 / 8.0), 7.5);
+        assert_close(map.wholes(5.0), 2.25);
+
+        let before = map.measure_at_index(1).unwrap();
+        assert_eq!(before.metre, Metre::from((4, 4)));
+        assert_close(before.start, 1.0);
+        let after = map.measure_at_index(3).unwrap();
+        assert_eq!(after.metre, Metre::from((7, 8)));
+        assert_close(after.start, 2.875);
+        assert_close(after.length, 0.875);
+
+        let measure = map.measure(2.9).unwrap();
+        assert_eq!(measure.index, 3);
+        assert_close(measure.start, 2.875);
+    }
+}
@@ -1,7 +1,10 @@
 use bevy::prelude::*;
+use bevy::ui::UiSystem;
+use leafwing_input_manager::plugin::InputManagerSystem;
 use leafwing_input_manager::prelude::ActionState;
 
-use crate::actions::UiButtonAction;
+use crate::actions::{NavigationAction, UiButtonAction};
+use crate::judgement::{Judgement, JudgementEvent};
 
 pub struct UiPlugin;
 impl Plugin for UiPlugin {
@@ -10,14 +13,29 @@ impl Plugin for UiPlugin {
     }
 
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup).add_systems(
-            Update,
-            (
-                ui_button_interaction,
-                menu_button_interaction,
-                update_ui_button_icon,
-            ),
-        );
+        app.add_systems(Startup, startup)
+            .add_systems(
+                PreUpdate,
+                navigate_focus
+                    .after(UiSystem::Focus)
+                    .after(InputManagerSystem::Update),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        send_ui_button_presses,
+                        evr_ui_button_presses,
+                        evr_judgement_flash,
+                        animate_ui_buttons,
+                        update_ui_button_icon,
+                    )
+                        .chain(),
+                    (menu_button_interaction, highlight_focus).chain(),
+                ),
+            )
+            .init_resource::<UiFocus>()
+            .add_event::<UiButtonPressEvent>();
     }
 }
 
@@ -64,11 +82,11 @@ pub struct UiButtonNode;
 impl UiButtonNode {
     pub fn small() -> Node {
         Node {
-            width: Val::Px(170.0),
-            height: Val::Px(50.0),
+            width: Val::Px(57.0),
+            height: Val::Px(17.0),
             justify_content: JustifyContent::SpaceAround,
             align_items: AlignItems::Center,
-            padding: UiRect::all(Val::Px(5.)),
+            padding: UiRect::all(Val::Px(2.)),
             ..Default::default()
         }
     }
@@ -168,9 +186,41 @@ impl UiButtonState {
             Misc => 6,
         }
     }
+
+    // Each icon in the atlas is followed by its pressed frame
+    pub fn icon(&self, pressed: bool) -> usize {
+        self.index() + usize::from(pressed)
+    }
+}
+
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct UiButtonPressed(pub bool);
+
+// Seconds the button takes to spring back after a press, and scale it springs back from
+const PRESS_SECONDS: f32 = 0.08;
+const PRESS_SCALE: f32 = 0.85;
+const FLASH_SECONDS: f32 = 0.15;
+
+#[derive(Component, Default)]
+pub struct UiButtonAnimation {
+    press: Timer,
+    flash: Timer,
+    flash_color: Color,
 }
 
-pub const UI_BUTTON_LENGTH: usize = 8;
+#[derive(Event, Clone, Copy, Debug)]
+pub struct UiButtonPressEvent {
+    pub action: UiButtonAction,
+    pub pressed: bool,
+}
+
+// Buttons that can be reached with `NavigationAction`s as well as the mouse
+#[derive(Component)]
+pub struct Focusable;
+
+// The focused button is highlighted like a hovered one and pressed by `NavigationAction::Confirm`
+#[derive(Resource, Default, Deref, DerefMut, PartialEq)]
+pub struct UiFocus(pub Option<Entity>);
 
 // SYSTEMS
 
@@ -210,42 +260,230 @@ fn menu_button_interaction(
     }
 }
 
-fn ui_button_interaction(
-    mut query_button_node: Query<(&mut ImageNode, &UiButton)>,
+// Moves focus to the closest focusable button in the pressed direction, follows the mouse, and
+// presses the focused button on confirm. Runs right after bevy's own `Interaction` update so the
+// press is seen by every system reacting to `Interaction::Pressed` this frame, and releases it
+// again the frame after like a mouse click would.
+fn navigate_focus(
+    mut focus: ResMut<UiFocus>,
+    mut pressed: Local<Option<Entity>>,
+    query_navigation: Query<&ActionState<NavigationAction>>,
+    mut query_focusable: Query<
+        (Entity, &GlobalTransform, &mut Interaction, &ViewVisibility),
+        With<Focusable>,
+    >,
+) {
+    if let Some(Ok((_, _, mut interaction, _))) =
+        pressed.take().map(|entity| query_focusable.get_mut(entity))
+    {
+        if *interaction == Interaction::Pressed {
+            *interaction = Interaction::None;
+        }
+    }
+    if focus.is_some_and(|entity| !query_focusable.contains(entity)) {
+        focus.set_if_neq(UiFocus(None));
+    }
+    for (entity, _, interaction, _) in &mut query_focusable {
+        if interaction.is_changed() && *interaction != Interaction::None {
+            focus.set_if_neq(UiFocus(Some(entity)));
+        }
+    }
+    let Ok(action_state) = query_navigation.get_single() else {
+        return;
+    };
+
+    let direction = [
+        (NavigationAction::Up, Vec2::NEG_Y),
+        (NavigationAction::Down, Vec2::Y),
+        (NavigationAction::Left, Vec2::NEG_X),
+        (NavigationAction::Right, Vec2::X),
+    ]
+    .into_iter()
+    .find(|(action, _)| action_state.just_pressed(action))
+    .map(|(_, direction)| direction);
+    if let Some(direction) = direction {
+        let visible = query_focusable
+            .iter()
+            .filter(|(_, _, _, visibility)| visibility.get())
+            .map(|(entity, transform, _, _)| (entity, transform.translation().truncate()));
+        let current = focus.and_then(|entity| {
+            query_focusable
+                .get(entity)
+                .ok()
+                .map(|(_, transform, _, _)| transform.translation().truncate())
+        });
+        let next = match current {
+            // Prefer buttons straight ahead over ones off to the side
+            Some(current) => visible
+                .filter_map(|(entity, position)| {
+                    let delta = position - current;
+                    let along = delta.dot(direction);
+                    let across = delta.perp_dot(direction).abs();
+                    (along > 0.0).then_some((entity, along + 2.0 * across))
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(entity, _)| entity),
+            // Nothing focused yet, start from the top left
+            None => visible
+                .min_by(|(_, a), (_, b)| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)))
+                .map(|(entity, _)| entity),
+        };
+        if next.is_some() {
+            focus.set_if_neq(UiFocus(next));
+        }
+    }
+
+    if action_state.just_pressed(&NavigationAction::Confirm) {
+        if let Some(Ok((_, _, mut interaction, _))) =
+            focus.map(|entity| query_focusable.get_mut(entity))
+        {
+            *interaction = Interaction::Pressed;
+            *pressed = **focus;
+        }
+    }
+}
+
+// Keeps the focused button highlighted, on top of the colours set for the mouse
+fn highlight_focus(
+    focus: Res<UiFocus>,
+    mut query_focusable: Query<
+        (
+            Entity,
+            Ref<Interaction>,
+            &mut BackgroundColor,
+            &mut BorderColor,
+            &Children,
+        ),
+        With<Focusable>,
+    >,
+    mut query_text_color: Query<&mut TextColor>,
+) {
+    for (entity, interaction, mut background_color, mut border_color, children) in
+        &mut query_focusable
+    {
+        if !focus.is_changed() && !interaction.is_changed() {
+            continue;
+        }
+        let focused = **focus == Some(entity);
+        let (background, border, text) = match *interaction {
+            Interaction::Pressed => (
+                UiBackgroundColor::default().pressed,
+                UiBorderColor::default().pressed,
+                UiTextColor::default().pressed,
+            ),
+            Interaction::Hovered => (
+                UiBackgroundColor::default().hovered,
+                UiBorderColor::default().hovered,
+                UiTextColor::default().hovered,
+            ),
+            Interaction::None if focused => (
+                UiBackgroundColor::default().hovered,
+                UiBorderColor::default().hovered,
+                UiTextColor::default().hovered,
+            ),
+            Interaction::None => (
+                UiBackgroundColor::default().normal,
+                UiBorderColor::default().normal,
+                UiTextColor::default().normal,
+            ),
+        };
+        background_color.0 = background.srgb();
+        border_color.0 = border.srgb();
+        if let Some(mut text_color) = children
+            .first()
+            .and_then(|child| query_text_color.get_mut(*child).ok())
+        {
+            text_color.0 = text.srgb();
+        }
+    }
+}
+
+fn send_ui_button_presses(
     query_button_action: Query<&ActionState<UiButtonAction>>,
+    mut evw_button_press: EventWriter<UiButtonPressEvent>,
+) {
+    let Ok(action_state) = query_button_action.get_single() else {
+        return;
+    };
+    for action in UiButtonAction::array() {
+        if action_state.just_pressed(&action) {
+            info!("[PRESSED] Button: {action:?}");
+            evw_button_press.send(UiButtonPressEvent {
+                action,
+                pressed: true,
+            });
+        }
+        if action_state.just_released(&action) {
+            info!("[RELEASED] Button: {action:?}");
+            evw_button_press.send(UiButtonPressEvent {
+                action,
+                pressed: false,
+            });
+        }
+    }
+}
+
+fn evr_ui_button_presses(
+    mut query_button: Query<(&UiButton, &mut UiButtonPressed, &mut UiButtonAnimation)>,
+    mut evr_button_press: EventReader<UiButtonPressEvent>,
 ) {
-    if let Ok(action_state) = query_button_action.get_single() {
-        for button in UiButtonAction::array() {
-            if action_state.just_pressed(&button) {
-                info!("[PRESSED] Button: {button:?}");
-                for (mut button_node, ui_button) in &mut query_button_node {
-                    if ***ui_button == button.index() {
-                        if let Some(atlas) = &mut button_node.texture_atlas {
-                            atlas.index = (atlas.index + 1) % UI_BUTTON_LENGTH;
-                        }
-                    }
-                }
+    for ev in evr_button_press.read() {
+        for (ui_button, mut pressed, mut animation) in &mut query_button {
+            if ***ui_button != ev.action.index() {
+                continue;
             }
-            if action_state.just_released(&button) {
-                info!("[RELEASED] Button: {button:?}");
-                for (mut button_node, ui_button) in &mut query_button_node {
-                    if ***ui_button == button.index() {
-                        if let Some(atlas) = &mut button_node.texture_atlas {
-                            atlas.index = (atlas.index - 1) % UI_BUTTON_LENGTH;
-                        }
-                    }
-                }
+            **pressed = ev.pressed;
+            if ev.pressed {
+                animation.press = Timer::from_seconds(PRESS_SECONDS, TimerMode::Once);
+            }
+        }
+    }
+}
+
+fn evr_judgement_flash(
+    mut query_button: Query<(&UiButton, &mut UiButtonAnimation)>,
+    mut evr_judgement: EventReader<JudgementEvent>,
+) {
+    for ev in evr_judgement.read() {
+        let color = match ev.judgement {
+            Judgement::Perfect => Palette::Lighter,
+            Judgement::Great => Palette::Light,
+            Judgement::Good => Palette::Dark,
+            Judgement::Miss => Palette::Darker,
+        };
+        for (ui_button, mut animation) in &mut query_button {
+            if ***ui_button == ev.action.index() {
+                animation.flash = Timer::from_seconds(FLASH_SECONDS, TimerMode::Once);
+                animation.flash_color = color.srgb();
             }
         }
     }
 }
 
+fn animate_ui_buttons(
+    time: Res<Time>,
+    mut query_button: Query<(&mut UiButtonAnimation, &mut Transform, &mut ImageNode)>,
+) {
+    for (mut animation, mut transform, mut button_node) in &mut query_button {
+        animation.press.tick(time.delta());
+        animation.flash.tick(time.delta());
+        let scale = PRESS_SCALE + (1.0 - PRESS_SCALE) * animation.press.fraction();
+        transform.scale = Vec3::new(scale, scale, 1.0);
+        button_node.color = animation
+            .flash_color
+            .mix(&Color::WHITE, animation.flash.fraction());
+    }
+}
+
 fn update_ui_button_icon(
-    mut query_button_node: Query<(&mut ImageNode, &UiButtonState), Changed<UiButtonState>>,
+    mut query_button_node: Query<(&mut ImageNode, &UiButtonState, &UiButtonPressed)>,
 ) {
-    for (mut button_node, state) in &mut query_button_node {
+    for (mut button_node, state, pressed) in &mut query_button_node {
+        let index = state.icon(**pressed);
         if let Some(atlas) = &mut button_node.texture_atlas {
-            atlas.index = state.index();
+            if atlas.index != index {
+                atlas.index = index;
+            }
         }
     }
 }